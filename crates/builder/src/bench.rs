@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use cb_builder::benchmark::{self, BenchConfig, BenchTarget};
+use cb_builder::providers::providers::Providers;
+use clap::Parser;
+
+// Run a prompt set across providers and models and write a JSON and a
+// Markdown report
+#[derive(Parser, Debug)]
+#[command(name = "bench")]
+struct Args {
+    #[arg(
+        short,
+        long,
+        help = "Benchmark config, defaults to $CONFIG_ROOT_DIR/bench.toml"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        help = "`provider` or `provider:model`, replaces the targets of the config"
+    )]
+    target: Vec<String>,
+    #[arg(short = 'j', long, help = "Requests in flight at the same time")]
+    concurrency: Option<usize>,
+    #[arg(
+        short,
+        long,
+        help = "Report directory, defaults to $CONFIG_ROOT_DIR/bench"
+    )]
+    out: Option<PathBuf>,
+}
+
+// Group `provider:model` arguments by provider, keeping their order
+fn parse_targets(args: &[String]) -> Vec<BenchTarget> {
+    let mut targets: Vec<BenchTarget> = Vec::new();
+    for arg in args {
+        // Ollama model names contain `:` too, only the first one separates
+        let (provider, model) = match arg.split_once(':') {
+            Some((provider, model)) => (provider, Some(model.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let index = match targets.iter().position(|t| t.provider == provider) {
+            Some(index) => index,
+            None => {
+                targets.push(BenchTarget {
+                    provider: provider.to_string(),
+                    models: Vec::new(),
+                });
+                targets.len() - 1
+            }
+        };
+        targets[index].models.extend(model);
+    }
+    targets
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let providers = Providers::load()?;
+    let config_root = Providers::get_config_path()?
+        .parent()
+        .map(PathBuf::from)
+        .unwrap_or_default();
+
+    let config_path = args
+        .config
+        .unwrap_or_else(|| config_root.join("bench.toml"));
+    let mut config = if config_path.exists() {
+        println!("Using {}", config_path.display());
+        BenchConfig::load_from(&config_path)?
+    } else {
+        // Every model of the default provider with the built-in prompts
+        BenchConfig::new(parse_targets(std::slice::from_ref(
+            &providers.default_provider,
+        )))
+    };
+    if !args.target.is_empty() {
+        config.targets = parse_targets(&args.target);
+    }
+    if let Some(concurrency) = args.concurrency {
+        config.concurrency = concurrency;
+    }
+
+    println!(
+        "Running {} prompts on {} targets, {} at a time",
+        config.prompts.len(),
+        config.targets.len(),
+        config.concurrency
+    );
+    let report = benchmark::run(&providers, &config).await?;
+    println!("{}", report.to_markdown());
+
+    let out = args.out.unwrap_or_else(|| config_root.join("bench"));
+    let (json_path, md_path) = report.save(&out)?;
+    println!("Wrote {} and {}", json_path.display(), md_path.display());
+    Ok(())
+}
@@ -0,0 +1,458 @@
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::provider::Provider;
+use crate::providers::providers::Providers;
+use crate::providers::structured;
+use crate::tools::agent_response::AgentResponse;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// One prompt of the benchmark set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchPrompt {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub prompt: String,
+    // Sent as the response schema, answers are validated against it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+}
+
+// Provider to benchmark, with every usable model when `models` is empty
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchTarget {
+    pub provider: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+}
+
+// Contents of bench.toml
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchConfig {
+    // Requests in flight at the same time, across all providers
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    pub targets: Vec<BenchTarget>,
+    #[serde(default = "default_prompts")]
+    pub prompts: Vec<BenchPrompt>,
+}
+
+// Outcome of one prompt on one model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchRun {
+    pub provider: String,
+    pub model: String,
+    pub prompt: String,
+    pub latency_ms: u64,
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    // Completion tokens over the whole request time, so prompt processing,
+    // time to first token and the network are included
+    pub overall_tokens_per_sec: Option<f64>,
+    // None when the prompt has no schema or the request failed
+    pub schema_valid: Option<bool>,
+    pub finish_reason: Option<String>,
+    pub error: Option<String>,
+}
+
+// Averages over the successful runs of one model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchSummary {
+    pub provider: String,
+    pub model: String,
+    pub runs: usize,
+    pub errors: usize,
+    pub avg_latency_ms: Option<u64>,
+    pub avg_overall_tokens_per_sec: Option<f64>,
+    pub schema_passed: usize,
+    pub schema_checked: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    // Unix timestamp in seconds
+    pub started_at: u64,
+    pub concurrency: usize,
+    pub runs: Vec<BenchRun>,
+    pub summary: Vec<BenchSummary>,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+fn default_prompts() -> Vec<BenchPrompt> {
+    vec![
+        BenchPrompt {
+            name: "sum".to_string(),
+            system: None,
+            prompt: "write program in rust that sums 2 numbers and print the result".to_string(),
+            schema: None,
+        },
+        BenchPrompt {
+            name: "agent_response".to_string(),
+            system: Some("You are a coding agent. Answer with JSON only.".to_string()),
+            prompt: "Create a rust project that prints the first 10 fibonacci numbers".to_string(),
+            schema: Some(AgentResponse::schema()),
+        },
+    ]
+}
+
+impl BenchConfig {
+    // Benchmark `targets` with the default prompt set
+    pub fn new(targets: Vec<BenchTarget>) -> Self {
+        Self {
+            concurrency: default_concurrency(),
+            temperature: None,
+            max_tokens: None,
+            targets,
+            prompts: default_prompts(),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ProviderError> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            ProviderError::Configuration(format!("Cannot read {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&content)
+            .map_err(|e| ProviderError::Configuration(format!("Invalid {}: {}", path.display(), e)))
+    }
+
+    // Every selected provider/model pair. Missing and embedding models are
+    // skipped unless they are named explicitly.
+    fn models(&self, providers: &Providers) -> Result<Vec<(Provider, String)>, ProviderError> {
+        let mut models = Vec::new();
+        for target in &self.targets {
+            let mut provider = providers
+                .get_by_name(&target.provider)
+                .ok_or_else(|| {
+                    ProviderError::Configuration(format!("Unknown provider: {}", target.provider))
+                })?
+                .clone();
+            // Cached answers would make every model look instant
+            provider.cache = None;
+            let names: Vec<String> = if target.models.is_empty() {
+                provider
+                    .models
+                    .iter()
+                    .filter(|m| !m.missing && !m.embedding)
+                    .map(|m| m.name.clone())
+                    .collect()
+            } else {
+                target.models.clone()
+            };
+            models.extend(names.into_iter().map(|name| (provider.clone(), name)));
+        }
+        Ok(models)
+    }
+}
+
+// Run every prompt on every selected model, at most `config.concurrency`
+// requests at a time. Failed requests are recorded in the report.
+pub async fn run(
+    providers: &Providers,
+    config: &BenchConfig,
+) -> Result<BenchReport, ProviderError> {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let models = config.models(providers)?;
+    let jobs = models
+        .iter()
+        .flat_map(|model| config.prompts.iter().map(move |prompt| (model, prompt)));
+    let mut runs: Vec<(usize, BenchRun)> = futures::stream::iter(jobs.enumerate())
+        .map(|(index, ((provider, model), prompt))| async move {
+            (index, run_one(provider, model, prompt, config).await)
+        })
+        .buffer_unordered(config.concurrency.max(1))
+        .collect()
+        .await;
+    // Keep the order of the config instead of the order of completion
+    runs.sort_by_key(|(index, _)| *index);
+    let runs: Vec<BenchRun> = runs.into_iter().map(|(_, run)| run).collect();
+    Ok(BenchReport {
+        started_at,
+        concurrency: config.concurrency,
+        summary: summarize(&runs),
+        runs,
+    })
+}
+
+async fn run_one(
+    provider: &Provider,
+    model: &str,
+    prompt: &BenchPrompt,
+    config: &BenchConfig,
+) -> BenchRun {
+    let mut run = BenchRun {
+        provider: provider.name.clone(),
+        model: model.to_string(),
+        prompt: prompt.name.clone(),
+        latency_ms: 0,
+        prompt_tokens: None,
+        completion_tokens: None,
+        overall_tokens_per_sec: None,
+        schema_valid: None,
+        finish_reason: None,
+        error: None,
+    };
+    let mut messages = Vec::new();
+    if let Some(system) = &prompt.system {
+        messages.push(ChatMessage::system(system));
+    }
+    messages.push(ChatMessage::user(&prompt.prompt));
+
+    let start = Instant::now();
+    let chat = provider
+        .with_model(model)
+        .ok_or_else(|| ProviderError::Configuration(format!("Unknown model: {}", model)))
+        .and_then(|builder| builder.build());
+    let result = match chat {
+        Ok(chat) => {
+            chat.complete(
+                messages,
+                None,
+                config.temperature,
+                config.max_tokens,
+                prompt.schema.clone(),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let elapsed = start.elapsed();
+    run.latency_ms = elapsed.as_millis() as u64;
+
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            run.error = Some(e.to_string());
+            return run;
+        }
+    };
+    if let Some(usage) = &result.usage {
+        run.prompt_tokens = Some(usage.prompt_tokens);
+        run.completion_tokens = Some(usage.completion_tokens);
+        if usage.completion_tokens > 0 && !elapsed.is_zero() {
+            run.overall_tokens_per_sec =
+                Some(usage.completion_tokens as f64 / elapsed.as_secs_f64());
+        }
+    }
+    run.finish_reason = result.finish_reason().map(str::to_string);
+    if let Some(schema) = &prompt.schema {
+        match jsonschema::validator_for(schema) {
+            Ok(validator) => {
+                let content = result.content().unwrap_or_default();
+                run.schema_valid = Some(structured::validate(&validator, content).is_ok());
+            }
+            Err(e) => run.error = Some(format!("Invalid schema for {}: {}", prompt.name, e)),
+        }
+    }
+    run
+}
+
+fn summarize(runs: &[BenchRun]) -> Vec<BenchSummary> {
+    let mut by_model: BTreeMap<(&str, &str), Vec<&BenchRun>> = BTreeMap::new();
+    for run in runs {
+        by_model
+            .entry((&run.provider, &run.model))
+            .or_default()
+            .push(run);
+    }
+    by_model
+        .into_iter()
+        .map(|((provider, model), runs)| {
+            let ok: Vec<&BenchRun> = runs.iter().copied().filter(|r| r.error.is_none()).collect();
+            let speeds: Vec<f64> = ok.iter().filter_map(|r| r.overall_tokens_per_sec).collect();
+            let checked: Vec<bool> = runs.iter().filter_map(|r| r.schema_valid).collect();
+            BenchSummary {
+                provider: provider.to_string(),
+                model: model.to_string(),
+                runs: runs.len(),
+                errors: runs.len() - ok.len(),
+                avg_latency_ms: (!ok.is_empty())
+                    .then(|| ok.iter().map(|r| r.latency_ms).sum::<u64>() / ok.len() as u64),
+                avg_overall_tokens_per_sec: (!speeds.is_empty())
+                    .then(|| speeds.iter().sum::<f64>() / speeds.len() as f64),
+                schema_passed: checked.iter().filter(|valid| **valid).count(),
+                schema_checked: checked.len(),
+            }
+        })
+        .collect()
+}
+
+impl BenchReport {
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!(
+            "# Benchmark {}\n\n| Provider | Model | Runs | Errors | Avg latency (ms) | Avg overall tok/s | Schema |\n|---|---|---|---|---|---|---|\n",
+            self.started_at
+        );
+        for s in &self.summary {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} |\n",
+                s.provider,
+                s.model,
+                s.runs,
+                s.errors,
+                or_dash(s.avg_latency_ms),
+                or_dash(s.avg_overall_tokens_per_sec.map(|t| format!("{:.1}", t))),
+                if s.schema_checked == 0 {
+                    "-".to_string()
+                } else {
+                    format!("{}/{}", s.schema_passed, s.schema_checked)
+                },
+            ));
+        }
+        md.push_str("\n## Runs\n\n| Provider | Model | Prompt | Latency (ms) | Tokens | Overall tok/s | Schema valid | Finish | Error |\n|---|---|---|---|---|---|---|---|---|\n");
+        for r in &self.runs {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {} |\n",
+                r.provider,
+                r.model,
+                r.prompt,
+                r.latency_ms,
+                or_dash(r.completion_tokens),
+                or_dash(r.overall_tokens_per_sec.map(|t| format!("{:.1}", t))),
+                or_dash(r.schema_valid),
+                or_dash(r.finish_reason.as_deref()),
+                // Pipes and newlines would break the table
+                or_dash(
+                    r.error
+                        .as_ref()
+                        .map(|e| e.replace('|', "\\|").replace('\n', " "))
+                ),
+            ));
+        }
+        md
+    }
+
+    // Write `bench-<started_at>.json` and `.md` into `dir`
+    pub fn save(&self, dir: &Path) -> std::io::Result<(PathBuf, PathBuf)> {
+        std::fs::create_dir_all(dir)?;
+        let json_path = dir.join(format!("bench-{}.json", self.started_at));
+        let md_path = dir.join(format!("bench-{}.md", self.started_at));
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(&json_path, json)?;
+        std::fs::write(&md_path, self.to_markdown())?;
+        Ok((json_path, md_path))
+    }
+}
+
+fn or_dash<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::provider::{ApiType, ProviderType};
+    use mockito::Matcher;
+    use serde_json::json;
+
+    fn provider(server: &mockito::Server) -> Provider {
+        let mut provider = Provider::provider(ProviderType::LmStudio);
+        provider.name = "local".to_string();
+        provider.base_url = format!("{}/v1", server.url());
+        provider.api_type = ApiType::OpenAI;
+        provider.models = vec!["fast".into(), "broken".into()];
+        provider
+    }
+
+    #[tokio::test]
+    async fn test_run_records_every_prompt() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(json!({"model": "fast"})))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "choices": [{"message": {"role": "assistant", "content": "{\"a\": 1}"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 10, "total_tokens": 15}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(json!({"model": "broken"})))
+            .with_status(400)
+            .with_body(r#"{"error": "no such model"}"#)
+            .create_async()
+            .await;
+
+        let providers = Providers {
+            providers: vec![provider(&server)],
+            default_provider: "local".to_string(),
+            routes: Vec::new(),
+        };
+        let mut config = BenchConfig::new(vec![BenchTarget {
+            provider: "local".to_string(),
+            models: Vec::new(),
+        }]);
+        config.prompts = vec![
+            BenchPrompt {
+                name: "plain".to_string(),
+                system: None,
+                prompt: "hi".to_string(),
+                schema: None,
+            },
+            BenchPrompt {
+                name: "typed".to_string(),
+                system: None,
+                prompt: "give me a".to_string(),
+                schema: Some(json!({"type": "object", "required": ["b"]})),
+            },
+        ];
+        let report = run(&providers, &config).await.unwrap();
+
+        let order: Vec<(&str, &str)> = report
+            .runs
+            .iter()
+            .map(|r| (r.model.as_str(), r.prompt.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("fast", "plain"),
+                ("fast", "typed"),
+                ("broken", "plain"),
+                ("broken", "typed")
+            ]
+        );
+        assert_eq!(report.runs[0].completion_tokens, Some(10));
+        assert!(report.runs[0].overall_tokens_per_sec.is_some());
+        assert_eq!(report.runs[0].schema_valid, None);
+        assert_eq!(report.runs[1].schema_valid, Some(false));
+        assert!(report.runs[2].error.is_some());
+
+        let broken = &report.summary[0];
+        assert_eq!((broken.model.as_str(), broken.errors), ("broken", 2));
+        assert_eq!(broken.avg_latency_ms, None);
+        let fast = &report.summary[1];
+        assert_eq!((fast.schema_passed, fast.schema_checked), (0, 1));
+
+        let md = report.to_markdown();
+        assert!(md.contains("| local | fast | 2 | 0 |"));
+        assert!(md.contains("| 0/1 |"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_provider() {
+        let config = BenchConfig::new(vec![BenchTarget {
+            provider: "nope".to_string(),
+            models: Vec::new(),
+        }]);
+        assert!(run(&Providers::default(), &config).await.is_err());
+    }
+}
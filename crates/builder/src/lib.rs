@@ -1,3 +1,4 @@
+pub mod benchmark;
 pub mod project;
 pub mod providers;
 pub mod tools;
@@ -8,7 +9,7 @@ mod tests {
     #[test]
     fn test_example_from_prompt() {
         use super::providers::providers::Providers;
-        let providers = Providers::load();
+        let providers = Providers::load().unwrap();
         println!("Providers: {:?}", providers);
     }
 }
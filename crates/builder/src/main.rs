@@ -1,113 +1,107 @@
-use std::time::Duration;
+use std::io::Write;
 
+use cb_builder::providers::chat::ChatProvider;
 use cb_builder::providers::openai::ChatMessage;
 use cb_builder::providers::providers::Providers;
 use cb_builder::tools;
+use futures::StreamExt;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load providers configuration
-    let providers = Providers::load();
+    let mut providers = Providers::load()?;
     println!("Loaded {} providers", providers.providers.len());
 
-    // Get the LmStudio provider
-    let lm_studio = providers
-        .get_by_name("LmStudio")
-        .expect("LmStudio provider not found");
-    println!("Using provider: {}", lm_studio.name);
-    println!("Available models: {:?}\n", lm_studio.models);
+    // Refresh the model list of the default provider from the running server.
+    // providers.toml is only rewritten with --save-models.
+    let save_models = std::env::args().any(|arg| arg == "--save-models");
+    let default_provider = providers.default_provider.clone();
+    match providers.sync_models(&default_provider).await {
+        Ok(sync) => {
+            println!(
+                "Synced models: added {:?}, missing {:?}",
+                sync.added, sync.missing
+            );
+            if save_models && !sync.is_empty() {
+                providers.save()?;
+            }
+        }
+        Err(e) => println!("Could not sync {} models: {}", default_provider, e),
+    }
+
+    // The "coder" route tries its provider/model pairs in order until one answers.
+    // LM Studio fixes the context size when it loads a model and its OpenAI
+    // compatible API rejects `ctx`, only the Ollama target sets it (params.ctx
+    // in providers.toml).
+    let coder = providers.route("coder")?;
+    println!("Using route: coder (first model: {})", coder.model());
 
     // Define our simple prompt
     let prompt = "write program in rust that sums 2 numbers and print the result";
     println!("Prompt: {}", prompt);
     println!("-----------------------------");
     println!("schema: {}", tools::agent_response::AgentResponse::schema());
-    // Example with a single model and customized parameters
-    if let Some(model_builder) =
-        lm_studio.with_model("jbaron34_-_qwen2.5-0.5b-bebop-reranker-newer-small")
-    {
-        println!("\nQuerying model: granite3.2 with custom parameters");
 
-        // Use the builder pattern to customize parameters
-        let provider = model_builder
-            .with_temperature(0.3) // Lower temperature for more deterministic answers
-            .with_max_tokens(100) // Limit response length
-            .with_top_p(0.95)
-            .with_ctx(9000) // Slightly adjust nucleus sampling
-            .build()?;
-
-        // Create messages for the chat completion
-        let messages = vec![ChatMessage::user(prompt)];
-
-        // Query the model
-        match provider
-            .chat_completion(
-                messages,
-                None,
-                None,
-                None,
-                Some(tools::agent_response::AgentResponse::schema()),
-            )
-            .await
-        {
-            Ok(response) => {
-                println!("Response from granite3.2 (custom params): {}", response);
-            }
-            Err(e) => {
-                println!("Error from granite3.2: {}", e);
+    // Stream the answer so the output shows up while the model is still generating
+    match coder
+        .chat_completion_stream(
+            vec![ChatMessage::user(prompt)],
+            None,
+            Some(0.3), // Lower temperature for more deterministic answers
+            Some(100), // Limit response length
+            None,
+        )
+        .await
+    {
+        Ok(mut stream) => {
+            print!("Streamed response: ");
+            let mut finish_reason = None;
+            while let Some(delta) = stream.next().await {
+                match delta {
+                    Ok(delta) => {
+                        print!("{}", delta.content);
+                        std::io::stdout().flush()?;
+                        finish_reason = delta.finish_reason.or(finish_reason);
+                    }
+                    Err(e) => {
+                        println!("\nStream error: {}", e);
+                        break;
+                    }
+                }
             }
+            println!("\n(finish reason: {:?})", finish_reason);
         }
-    } else {
-        println!("Model 'granite3.2' not found");
+        Err(e) => println!("Error: {}", e),
     }
 
-    // For each model in the LmStudio provider, create a request and get a response
-    for model_entry in &lm_studio.models {
-        println!("\nQuerying model: {}", model_entry.name);
-
-        // Get a model builder and build the provider with default settings
-        let provider = match lm_studio.with_model(&model_entry.name) {
-            Some(builder) => match builder.build() {
-                Ok(provider) => provider,
-                Err(e) => {
-                    println!("Error building provider for {}: {}", model_entry.name, e);
-                    continue;
-                }
-            },
-            None => {
-                println!("Error: Model {} not found", model_entry.name);
-                continue;
-            }
-        };
-
-        // Create messages for the chat completion
-        let messages = vec![ChatMessage::user(prompt)];
-
-        // Query the model with a timeout
-        let result = tokio::time::timeout(
-            Duration::from_secs(30), // 30 second timeout
-            provider.chat_completion(
-                messages,
-                None,
-                None,
-                None,
-                Some(tools::agent_response::AgentResponse::schema()),
-            ),
+    // Ask for a structured answer, targets returning invalid JSON are skipped
+    match coder
+        .complete(
+            vec![ChatMessage::user(prompt)],
+            None,
+            None,
+            None,
+            Some(tools::agent_response::AgentResponse::schema()),
         )
-        .await;
-
-        // Handle the result
-        match result {
-            Ok(Ok(response)) => {
-                println!("Response from {}: {}", model_entry.name, response);
-            }
-            Ok(Err(e)) => {
-                println!("Error from {}: {}", model_entry.name, e);
+        .await
+    {
+        Ok(result) => {
+            println!(
+                "Response from {}: {}",
+                result.model,
+                result.content().unwrap_or_default()
+            );
+            if let Some(usage) = result.usage {
+                println!(
+                    "(tokens: {} prompt + {} completion = {})",
+                    usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
+                );
             }
-            Err(_) => {
-                println!("Timeout waiting for response from {}", model_entry.name);
+            if result.is_truncated() {
+                println!("Warning: response was cut off by the max_tokens limit");
             }
         }
+        Err(e) => println!("Error: {}", e),
     }
 
     Ok(())
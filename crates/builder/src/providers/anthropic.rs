@@ -0,0 +1,974 @@
+use super::provider::ApiType;
+use crate::providers::api_key::CachedKey;
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::{CompletionChoice, CompletionResult, Usage};
+use crate::providers::config::{ProviderConfig, ProviderError};
+use crate::providers::content::{ContentPart, MessageContent};
+use crate::providers::openai::ChatMessage;
+use crate::providers::stream::{ChatDelta, ChatStream, sse_events};
+use crate::providers::tool_call::{ToolCall, ToolDefinition};
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Version header required by every Messages API request
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+// The Messages API has no server side default, so we need to always send one
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+// Name of the tool used to force structured output that matches a schema
+const RESPONSE_TOOL_NAME: &str = "response";
+
+// Anthropic provider implementation for the native Messages API
+#[derive(Debug, Clone)]
+pub struct AnthropicProvider {
+    client: Client,
+    config: Arc<ProviderConfig>,
+    api_base_url: String,
+    api_key: CachedKey,
+}
+
+// Request structures
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    // Extended thinking, only in responses. The signature would be needed to
+    // send it back, but reasoning is not kept in requests.
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct AnthropicMessage {
+    role: String,
+    content: Vec<ContentBlock>,
+}
+
+// The API rejects a `null` description, it has to be left out
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct AnthropicTool<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    input_schema: &'a serde_json::Value,
+}
+
+impl<'a> From<&'a ToolDefinition> for AnthropicTool<'a> {
+    fn from(tool: &'a ToolDefinition) -> Self {
+        AnthropicTool {
+            name: &tool.name,
+            description: tool.description.as_deref(),
+            input_schema: &tool.parameters,
+        }
+    }
+}
+
+// Response structures
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    model: String,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+// Streaming event payloads, only the fields we need
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    ContentBlockDelta {
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+    },
+    Error {
+        error: AnthropicErrorDetail,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    // Partial tool input, used for streamed structured output
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+    #[serde(default)]
+    has_more: bool,
+    last_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicErrorDetail {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+impl MessagesResponse {
+    // Map the response to the OpenAI style result with a single choice. With
+    // `structured` the input of the forced response tool becomes the content
+    // and text around it is dropped, otherwise text blocks are joined and
+    // tool_use blocks become tool_calls.
+    fn into_completion(self, structured: bool) -> Result<CompletionResult, ProviderError> {
+        let mut text = String::new();
+        let mut output = None;
+        let mut reasoning = String::new();
+        let mut calls = Vec::new();
+        for block in self.content {
+            match block {
+                ContentBlock::Text { text: t } => text.push_str(&t),
+                ContentBlock::Thinking { thinking, .. } => reasoning.push_str(&thinking),
+                ContentBlock::ToolUse { name, input, .. }
+                    if structured && name == RESPONSE_TOOL_NAME =>
+                {
+                    output = Some(input.to_string());
+                }
+                ContentBlock::ToolUse { id, name, input } => {
+                    calls.push(ToolCall::new(id, name, input.to_string()))
+                }
+                ContentBlock::Image { .. }
+                | ContentBlock::ToolResult { .. }
+                | ContentBlock::RedactedThinking { .. } => {}
+            }
+        }
+        if let Some(output) = output {
+            text = output;
+        }
+        if structured && text.is_empty() {
+            return Err(ProviderError::ResponseParsing(
+                "Anthropic response did not contain structured output".to_string(),
+            ));
+        }
+
+        let mut message = ChatMessage::assistant(text);
+        message.reasoning = (!reasoning.is_empty()).then_some(reasoning);
+        if !calls.is_empty() {
+            message.tool_calls = Some(calls);
+        }
+        Ok(CompletionResult {
+            id: self.id,
+            model: self.model,
+            created: None,
+            choices: vec![CompletionChoice {
+                index: 0,
+                message,
+                finish_reason: self.stop_reason,
+            }],
+            usage: self.usage.map(|u| Usage {
+                prompt_tokens: u.input_tokens,
+                completion_tokens: u.output_tokens,
+                total_tokens: u.input_tokens + u.output_tokens,
+            }),
+        })
+    }
+}
+
+// Split our OpenAI style messages into the top level `system` prompt and the
+// user/assistant turns. Consecutive turns of the same role are merged into a
+// single message with multiple content blocks. Tool calls become `tool_use`
+// blocks and `tool` messages become `tool_result` blocks of a user turn.
+fn split_messages(messages: Vec<ChatMessage>) -> (Option<String>, Vec<AnthropicMessage>) {
+    let mut system: Vec<String> = Vec::new();
+    let mut turns: Vec<AnthropicMessage> = Vec::new();
+
+    for message in messages {
+        let (role, blocks) = match message.role.as_str() {
+            "system" => {
+                system.push(message.content.into_text());
+                continue;
+            }
+            "tool" => (
+                "user".to_string(),
+                vec![ContentBlock::ToolResult {
+                    tool_use_id: message.tool_call_id.unwrap_or_default(),
+                    content: message.content.into_text(),
+                }],
+            ),
+            _ => {
+                let mut blocks = content_blocks(message.content);
+                for call in message.tool_calls.unwrap_or_default() {
+                    blocks.push(ContentBlock::ToolUse {
+                        id: call.id,
+                        name: call.function.name,
+                        input: serde_json::from_str(&call.function.arguments)
+                            .unwrap_or_else(|_| json!({})),
+                    });
+                }
+                (message.role, blocks)
+            }
+        };
+        // The API rejects turns without content
+        if blocks.is_empty() {
+            continue;
+        }
+        match turns.last_mut() {
+            Some(last) if last.role == role => last.content.extend(blocks),
+            _ => turns.push(AnthropicMessage {
+                role,
+                content: blocks,
+            }),
+        }
+    }
+
+    let system = if system.is_empty() {
+        None
+    } else {
+        Some(system.join("\n\n"))
+    };
+    (system, turns)
+}
+
+// Text and image blocks of a message. Images in data URLs are sent inline,
+// any other URL is left for Anthropic to fetch.
+fn content_blocks(content: MessageContent) -> Vec<ContentBlock> {
+    let parts = match content {
+        MessageContent::Text(text) if text.is_empty() => return Vec::new(),
+        MessageContent::Text(text) => return vec![ContentBlock::Text { text }],
+        MessageContent::Parts(parts) => parts,
+    };
+    parts
+        .into_iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => ContentBlock::Text { text },
+            ContentPart::ImageUrl { image_url } => {
+                let source = match image_url.as_base64() {
+                    Some((media_type, data)) => ImageSource::Base64 {
+                        media_type: media_type.to_string(),
+                        data: data.to_string(),
+                    },
+                    None => ImageSource::Url { url: image_url.url },
+                };
+                ContentBlock::Image { source }
+            }
+        })
+        .collect()
+}
+
+// Convert one event of the stream into a delta, skipping events without content
+fn parse_stream_event(data: &str) -> Option<Result<ChatDelta, ProviderError>> {
+    let event: StreamEvent = match serde_json::from_str(data) {
+        Ok(event) => event,
+        Err(e) => {
+            return Some(Err(ProviderError::ResponseParsing(format!(
+                "Failed to parse Anthropic stream event: {}",
+                e
+            ))));
+        }
+    };
+    match event {
+        StreamEvent::ContentBlockDelta { delta } => match delta {
+            BlockDelta::TextDelta { text } => Some(ChatDelta {
+                content: text,
+                ..Default::default()
+            }),
+            BlockDelta::ThinkingDelta { thinking } => Some(ChatDelta {
+                reasoning: thinking,
+                ..Default::default()
+            }),
+            BlockDelta::InputJsonDelta { partial_json } => Some(ChatDelta {
+                content: partial_json,
+                ..Default::default()
+            }),
+            BlockDelta::Other => None,
+        }
+        .map(Ok),
+        StreamEvent::MessageDelta { delta } => delta.stop_reason.map(|reason| {
+            Ok(ChatDelta {
+                finish_reason: Some(reason),
+                ..Default::default()
+            })
+        }),
+        StreamEvent::Error { error } => Some(Err(ProviderError::ApiCall(format!(
+            "Anthropic stream error {}: {}",
+            error.kind, error.message
+        )))),
+        StreamEvent::Other => None,
+    }
+}
+
+impl AnthropicProvider {
+    pub fn new(config: Arc<ProviderConfig>) -> Result<Self, ProviderError> {
+        if config.api_type != ApiType::Anthropic {
+            return Err(ProviderError::Configuration(
+                "Incorrect ApiType for AnthropicProvider".to_string(),
+            ));
+        }
+
+        let api_key = config.api_key.clone().map(CachedKey::new).ok_or_else(|| {
+            ProviderError::Configuration("Anthropic API key is missing".to_string())
+        })?;
+
+        let client = config.http.client(Duration::from_secs(120))?;
+
+        let api_base_url = config
+            .api_base_url
+            .clone()
+            .unwrap_or_else(|| "https://api.anthropic.com/v1".to_string());
+
+        Ok(AnthropicProvider {
+            client,
+            config,
+            api_base_url,
+            api_key,
+        })
+    }
+
+    // Build the Messages API body shared by the blocking and streaming requests
+    fn request_body(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> serde_json::Value {
+        let model = model_override.unwrap_or_else(|| self.config.model.clone());
+        let temperature = temperature_override.or(self.config.temperature);
+        let max_tokens = max_tokens_override
+            .or_else(|| self.config.max_tokens.map(|t| t as u32))
+            .unwrap_or(DEFAULT_MAX_TOKENS);
+
+        let (system, turns) = split_messages(messages);
+        let mut request_body = json!({
+            "model": model,
+            "max_tokens": max_tokens,
+            "messages": turns,
+        });
+
+        if let Some(system) = system {
+            request_body["system"] = json!(system);
+        }
+        if let Some(temp) = temperature {
+            request_body["temperature"] = json!(temp);
+        }
+        if let Some(top_p) = self.config.top_p {
+            request_body["top_p"] = json!(top_p);
+        }
+        if let Some(top_k) = self.config.top_k {
+            request_body["top_k"] = json!(top_k);
+        }
+        if let Some(stop) = &self.config.stop {
+            request_body["stop_sequences"] = json!(stop);
+        }
+        // The Messages API has no json_schema response format, so we force the
+        // model to call a single tool whose input schema is the requested schema
+        if let Some(schema) = response_schema {
+            request_body["tools"] = json!([{
+                "name": RESPONSE_TOOL_NAME,
+                "description": "Respond with output that matches this schema",
+                "input_schema": schema
+            }]);
+            request_body["tool_choice"] = json!({
+                "type": "tool",
+                "name": RESPONSE_TOOL_NAME
+            });
+        }
+        self.config.http.extend_body(&mut request_body);
+        request_body
+    }
+
+    // Send a request with the Anthropic auth headers, retrying according to the config
+    async fn send<F>(&self, build: F) -> Result<reqwest::Response, ProviderError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let api_key = self.api_key.get().await?;
+        self.config
+            .send(|| {
+                build()
+                    .header("x-api-key", &api_key)
+                    .header("anthropic-version", ANTHROPIC_VERSION)
+            })
+            .await
+    }
+
+    // POST a request body to the Messages API
+    async fn post_messages(
+        &self,
+        request_body: &serde_json::Value,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let url = format!("{}/messages", self.api_base_url);
+        self.send(|| {
+            self.client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .json(request_body)
+        })
+        .await
+    }
+
+    // Send a non-streaming request and parse the response
+    async fn execute(
+        &self,
+        request_body: &serde_json::Value,
+    ) -> Result<MessagesResponse, ProviderError> {
+        let response = self.post_messages(request_body).await?;
+        self.config
+            .unless_cancelled(response.json())
+            .await?
+            .map_err(|e| {
+                ProviderError::ResponseParsing(format!("Failed to parse Anthropic response: {}", e))
+            })
+    }
+}
+
+#[async_trait]
+impl ChatProvider for AnthropicProvider {
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let structured = response_schema.is_some();
+        let request_body = self.request_body(
+            messages,
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+        );
+        self.execute(&request_body)
+            .await?
+            .into_completion(structured)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        let mut request_body = self.request_body(
+            messages,
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+        );
+        request_body["stream"] = json!(true);
+        let response = self.post_messages(&request_body).await?;
+
+        let deltas = sse_events(response).filter_map(|event| async move {
+            match event {
+                Ok(event) => parse_stream_event(&event.data),
+                Err(e) => Some(Err(e)),
+            }
+        });
+        Ok(self.config.cancellable(Box::pin(deltas)))
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        let mut request_body = self.request_body(messages, None, None, None, None);
+        request_body["tools"] = json!(tools.iter().map(AnthropicTool::from).collect::<Vec<_>>());
+        self.execute(&request_body)
+            .await?
+            .into_completion(false)?
+            .into_message()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let url = format!("{}/models", self.api_base_url);
+        let mut ids = Vec::new();
+        let mut after_id: Option<String> = None;
+        // The list is paged, follow it until the last page
+        loop {
+            let response = self
+                .send(|| {
+                    let request = self.client.get(&url).query(&[("limit", "1000")]);
+                    match &after_id {
+                        Some(after_id) => request.query(&[("after_id", after_id)]),
+                        None => request,
+                    }
+                })
+                .await?;
+            let page: ModelList = self
+                .config
+                .unless_cancelled(response.json())
+                .await?
+                .map_err(|e| {
+                    ProviderError::ResponseParsing(format!(
+                        "Failed to parse Anthropic model list: {}",
+                        e
+                    ))
+                })?;
+            ids.extend(page.data.into_iter().map(|m| m.id));
+            match page.last_id {
+                Some(last_id) if page.has_more => after_id = Some(last_id),
+                _ => return Ok(ids),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::stream::collect_stream;
+    use mockito::Matcher;
+
+    fn provider(base_url: String) -> AnthropicProvider {
+        let config = ProviderConfig::new(ApiType::Anthropic, "claude-3-5-haiku-latest".to_string())
+            .with_api_key("test-key".to_string())
+            .with_api_base_url(base_url);
+        AnthropicProvider::new(Arc::new(config)).unwrap()
+    }
+
+    #[test]
+    fn test_split_messages() {
+        let (system, turns) = split_messages(vec![
+            ChatMessage::system("be brief"),
+            ChatMessage::user("hello"),
+            // Empty turns are dropped, the user turns around it merge
+            ChatMessage::assistant(""),
+            ChatMessage::user("again"),
+            ChatMessage::assistant("hi"),
+        ]);
+        assert_eq!(system.as_deref(), Some("be brief"));
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0].role, "user");
+        assert_eq!(turns[0].content.len(), 2);
+        assert_eq!(turns[1].role, "assistant");
+    }
+
+    #[test]
+    fn test_split_messages_with_images() {
+        let (_, turns) = split_messages(vec![ChatMessage::user(vec![
+            ContentPart::text("Compare these"),
+            ContentPart::image_bytes("image/png", b"png"),
+            ContentPart::image_url("https://example.com/ui.jpg"),
+        ])]);
+        assert_eq!(
+            serde_json::to_value(&turns[0].content).unwrap(),
+            json!([
+                {"type": "text", "text": "Compare these"},
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "cG5n"}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/ui.jpg"}}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_split_messages_with_tool_calls() {
+        let mut assistant = ChatMessage::assistant("");
+        assistant.tool_calls = Some(vec![ToolCall::new(
+            "toolu_1",
+            "read_file",
+            r#"{"file_path":"main.rs"}"#.to_string(),
+        )]);
+        let (_, turns) = split_messages(vec![
+            ChatMessage::user("show main.rs"),
+            assistant,
+            ChatMessage::tool("toolu_1", "fn main() {}"),
+        ]);
+        assert_eq!(turns.len(), 3);
+        assert_eq!(
+            turns[1].content,
+            vec![ContentBlock::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "read_file".to_string(),
+                input: json!({"file_path": "main.rs"}),
+            }]
+        );
+        assert_eq!(turns[2].role, "user");
+        assert_eq!(
+            turns[2].content,
+            vec![ContentBlock::ToolResult {
+                tool_use_id: "toolu_1".to_string(),
+                content: "fn main() {}".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_tools() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::PartialJson(json!({
+                "tools": [{"name": "read_file", "input_schema": {"type": "object"}}]
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "msg_04",
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": "Let me look."},
+                        {"type": "tool_use", "id": "toolu_2", "name": "read_file", "input": {"file_path": "main.rs"}}
+                    ],
+                    "stop_reason": "tool_use"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let tools = vec![ToolDefinition {
+            name: "read_file".to_string(),
+            description: Some("get content of a file".to_string()),
+            parameters: json!({"type": "object"}),
+        }];
+        let message = provider(format!("{}/v1", server.url()))
+            .chat_with_tools(vec![ChatMessage::user("show main.rs")], tools)
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Let me look.");
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls[0].function.name, "read_file");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&calls[0].function.arguments).unwrap(),
+            json!({"file_path": "main.rs"})
+        );
+    }
+
+    #[test]
+    fn test_tool_without_description() {
+        let tool = ToolDefinition {
+            name: "list_files".to_string(),
+            description: None,
+            parameters: json!({"type": "object"}),
+        };
+        assert_eq!(
+            serde_json::to_value(AnthropicTool::from(&tool)).unwrap(),
+            json!({"name": "list_files", "input_schema": {"type": "object"}})
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_api_type() {
+        let config = ProviderConfig::new(ApiType::OpenAI, "gpt4".to_string())
+            .with_api_key("test-key".to_string());
+        assert!(AnthropicProvider::new(Arc::new(config)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_header("x-api-key", "test-key")
+            .match_header("anthropic-version", ANTHROPIC_VERSION)
+            .match_body(Matcher::PartialJson(json!({
+                "model": "claude-3-5-haiku-latest",
+                "max_tokens": DEFAULT_MAX_TOKENS,
+                "system": "be brief",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "hello"}]}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "msg_01",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3-5-haiku-latest",
+                    "content": [{"type": "text", "text": "Hi there"}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 10, "output_tokens": 3}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let response = provider(format!("{}/v1", server.url()))
+            .chat_completion(
+                vec![ChatMessage::system("be brief"), ChatMessage::user("hello")],
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(response, "Hi there");
+    }
+
+    #[tokio::test]
+    async fn test_complete_maps_usage_and_stop_reason() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "msg_02",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3-5-haiku-20241022",
+                    "content": [
+                        {"type": "thinking", "thinking": "A fairy tale", "signature": "c2ln"},
+                        {"type": "text", "text": "Once upon"}
+                    ],
+                    "stop_reason": "max_tokens",
+                    "usage": {"input_tokens": 12, "output_tokens": 2}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let result = provider(format!("{}/v1", server.url()))
+            .complete(vec![ChatMessage::user("story")], None, None, Some(2), None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id, "msg_02");
+        assert_eq!(result.model, "claude-3-5-haiku-20241022");
+        assert_eq!(result.finish_reason(), Some("max_tokens"));
+        assert!(result.is_truncated());
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                prompt_tokens: 12,
+                completion_tokens: 2,
+                total_tokens: 14
+            })
+        );
+        assert_eq!(result.content(), Some("Once upon"));
+        assert_eq!(result.reasoning(), Some("A fairy tale"));
+    }
+
+    #[tokio::test]
+    async fn test_structured_output_uses_forced_tool() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::PartialJson(json!({
+                "tool_choice": {"type": "tool", "name": RESPONSE_TOOL_NAME}
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "msg_02",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3-5-haiku-latest",
+                    "content": [
+                        {"type": "text", "text": "Here it is:"},
+                        {
+                            "type": "tool_use",
+                            "id": "toolu_01",
+                            "name": RESPONSE_TOOL_NAME,
+                            "input": {"answer": 3}
+                        },
+                        {"type": "text", "text": "Let me know if you need more."}
+                    ],
+                    "stop_reason": "tool_use"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let schema = json!({"type": "object", "properties": {"answer": {"type": "integer"}}});
+        let response = provider(format!("{}/v1", server.url()))
+            .chat_completion(
+                vec![ChatMessage::user("1 + 2")],
+                None,
+                None,
+                None,
+                Some(schema),
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&response).unwrap(),
+            json!({"answer": 3})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream() {
+        let mut server = mockito::Server::new_async().await;
+        let body = [
+            r#"event: message_start
+data: {"type":"message_start","message":{"id":"msg_03","type":"message","role":"assistant","content":[]}}"#,
+            r#"event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            "event: ping\ndata: {\"type\": \"ping\"}",
+            r#"event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" there"}}"#,
+            r#"event: message_delta
+data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":2}}"#,
+            r#"event: message_stop
+data: {"type":"message_stop"}"#,
+        ]
+        .join("\n\n")
+            + "\n\n";
+        server
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(body)
+            .create_async()
+            .await;
+
+        let stream = provider(format!("{}/v1", server.url()))
+            .chat_completion_stream(vec![ChatMessage::user("hello")], None, None, None, None)
+            .await
+            .unwrap();
+        let collected = collect_stream(stream).await.unwrap();
+        assert_eq!(collected.message.content, "Hi there");
+        assert_eq!(collected.finish_reason.as_deref(), Some("end_turn"));
+    }
+
+    #[tokio::test]
+    async fn test_list_models() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("GET", "/v1/models")
+            .match_query(Matcher::UrlEncoded("limit".into(), "1000".into()))
+            .match_header("x-api-key", "test-key")
+            .with_status(200)
+            .with_body(
+                json!({
+                    "data": [{"id": "claude-sonnet-4-0", "type": "model"}],
+                    "has_more": true,
+                    "first_id": "claude-sonnet-4-0",
+                    "last_id": "claude-sonnet-4-0"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let second = server
+            .mock("GET", "/v1/models")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("limit".into(), "1000".into()),
+                Matcher::UrlEncoded("after_id".into(), "claude-sonnet-4-0".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "data": [{"id": "claude-3-5-haiku-latest", "type": "model"}],
+                    "has_more": false,
+                    "first_id": "claude-3-5-haiku-latest",
+                    "last_id": "claude-3-5-haiku-latest"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let models = provider(format!("{}/v1", server.url()))
+            .list_models()
+            .await
+            .unwrap();
+        assert_eq!(models, vec!["claude-sonnet-4-0", "claude-3-5-haiku-latest"]);
+        first.assert_async().await;
+        second.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_error_body_is_reported() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/messages")
+            .with_status(400)
+            .with_body(
+                json!({
+                    "type": "error",
+                    "error": {"type": "invalid_request_error", "message": "max_tokens: field required"}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let err = provider(format!("{}/v1", server.url()))
+            .chat_completion(vec![ChatMessage::user("hello")], None, None, None, None)
+            .await
+            .unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("invalid_request_error"));
+        assert!(message.contains("max_tokens: field required"));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::providers::config::ProviderError;
+use schemars::JsonSchema;
+use schemars::r#gen::SchemaGenerator;
+use schemars::schema::Schema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::OnceCell;
+
+// API key as written in providers.toml. Besides a literal key it can point
+// at where the key is kept:
+//
+// api_key = "env:OPENAI_API_KEY"
+// api_key = "file:~/.secrets/groq"
+// api_key = "cmd:pass show groq"
+//
+// Only the reference is ever serialized, the secret is read by `resolve`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum ApiKey {
+    Literal(String),
+    // Name of an environment variable
+    Env(String),
+    // Path to a file holding the key, `~/` is expanded
+    File(String),
+    // Command printing the key on stdout, run without a shell
+    Cmd(String),
+}
+
+impl Default for ApiKey {
+    fn default() -> Self {
+        ApiKey::Literal(String::new())
+    }
+}
+
+impl ApiKey {
+    pub fn parse(value: &str) -> Self {
+        if let Some(name) = value.strip_prefix("env:") {
+            ApiKey::Env(name.trim().to_string())
+        } else if let Some(path) = value.strip_prefix("file:") {
+            ApiKey::File(path.trim().to_string())
+        } else if let Some(command) = value.strip_prefix("cmd:") {
+            ApiKey::Cmd(command.trim().to_string())
+        } else {
+            ApiKey::Literal(value.to_string())
+        }
+    }
+
+    // The string written to providers.toml
+    pub fn reference(&self) -> String {
+        match self {
+            ApiKey::Literal(key) => key.clone(),
+            ApiKey::Env(name) => format!("env:{}", name),
+            ApiKey::File(path) => format!("file:{}", path),
+            ApiKey::Cmd(command) => format!("cmd:{}", command),
+        }
+    }
+
+    // Read the secret the key points at
+    pub async fn resolve(&self) -> Result<String, ProviderError> {
+        match self {
+            ApiKey::Literal(key) => Ok(key.clone()),
+            ApiKey::Env(name) => std::env::var(name).map_err(|_| {
+                ProviderError::Configuration(format!(
+                    "API key environment variable {} is not set",
+                    name
+                ))
+            }),
+            ApiKey::File(path) => {
+                let path = expand_home(path);
+                tokio::fs::read_to_string(&path)
+                    .await
+                    .map(|key| key.trim().to_string())
+                    .map_err(|e| {
+                        ProviderError::Configuration(format!(
+                            "Cannot read API key from {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })
+            }
+            ApiKey::Cmd(command) => run_command(command).await,
+        }
+    }
+}
+
+// Key of a provider, resolved on the first request and kept for the
+// following ones. Clones share the secret, a failed resolve is retried.
+#[derive(Clone)]
+pub struct CachedKey {
+    key: ApiKey,
+    secret: Arc<OnceCell<String>>,
+}
+
+impl CachedKey {
+    pub fn new(key: ApiKey) -> Self {
+        CachedKey {
+            key,
+            secret: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub async fn get(&self) -> Result<String, ProviderError> {
+        self.secret
+            .get_or_try_init(|| self.key.resolve())
+            .await
+            .cloned()
+    }
+}
+
+impl fmt::Debug for CachedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+async fn run_command(command: &str) -> Result<String, ProviderError> {
+    let error =
+        |msg: String| ProviderError::Configuration(format!("API key command failed: {}", msg));
+    let args = shlex::split(command).ok_or_else(|| error(format!("cannot parse `{}`", command)))?;
+    let (program, args) = args
+        .split_first()
+        .ok_or_else(|| error("empty command".to_string()))?;
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| error(format!("`{}`: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(error(format!(
+            "`{}` exited with {}",
+            program, output.status
+        )));
+    }
+    // Tools like `pass` put the secret on the first line
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+impl From<&str> for ApiKey {
+    fn from(value: &str) -> Self {
+        ApiKey::parse(value)
+    }
+}
+
+impl From<String> for ApiKey {
+    fn from(value: String) -> Self {
+        ApiKey::parse(&value)
+    }
+}
+
+// Literal keys are redacted, references are safe to show
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKey::Literal(key) if key.is_empty() => write!(f, "ApiKey(\"\")"),
+            ApiKey::Literal(_) => write!(f, "ApiKey(<redacted>)"),
+            other => write!(f, "ApiKey({:?})", other.reference()),
+        }
+    }
+}
+
+impl Serialize for ApiKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.reference())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(ApiKey::from)
+    }
+}
+
+// Written as a plain string in providers.toml
+impl JsonSchema for ApiKey {
+    fn schema_name() -> String {
+        "ApiKey".to_string()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let mut schema = String::json_schema(generator).into_object();
+        schema.metadata().description =
+            Some("API key, or a reference to it: env:VAR, file:PATH or cmd:COMMAND".to_string());
+        schema.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse_and_serialize() {
+        for value in [
+            "sk-123",
+            "env:OPENAI_API_KEY",
+            "file:~/.secrets/groq",
+            "cmd:pass show groq",
+        ] {
+            let key = ApiKey::from(value);
+            assert_eq!(serde_json::to_value(&key).unwrap(), value);
+        }
+        assert_eq!(
+            ApiKey::from("cmd:pass show groq"),
+            ApiKey::Cmd("pass show groq".to_string())
+        );
+    }
+
+    #[test]
+    fn test_debug_redacts_literal_keys() {
+        assert_eq!(
+            format!("{:?}", ApiKey::from("sk-secret")),
+            "ApiKey(<redacted>)"
+        );
+        assert_eq!(
+            format!("{:?}", ApiKey::from("env:GROQ_API_KEY")),
+            "ApiKey(\"env:GROQ_API_KEY\")"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        assert_eq!(ApiKey::from("sk-1").resolve().await.unwrap(), "sk-1");
+        assert_eq!(
+            ApiKey::from("env:HOME").resolve().await.unwrap(),
+            std::env::var("HOME").unwrap()
+        );
+        assert!(
+            ApiKey::from("env:CB_SURELY_MISSING_KEY")
+                .resolve()
+                .await
+                .is_err()
+        );
+
+        let tmp_dir = TempDir::new("api-key-test").unwrap();
+        let path = tmp_dir.path().join("groq");
+        std::fs::write(&path, "gsk-file\n").unwrap();
+        let key = ApiKey::from(format!("file:{}", path.display()));
+        assert_eq!(key.resolve().await.unwrap(), "gsk-file");
+
+        assert_eq!(
+            ApiKey::from("cmd:echo 'gsk cmd'").resolve().await.unwrap(),
+            "gsk cmd"
+        );
+        assert!(ApiKey::from("cmd:false").resolve().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cached_key() {
+        let tmp_dir = TempDir::new("api-key-test").unwrap();
+        let path = tmp_dir.path().join("groq");
+        let key = CachedKey::new(ApiKey::from(format!("file:{}", path.display())));
+        assert!(key.get().await.is_err());
+
+        std::fs::write(&path, "gsk-first").unwrap();
+        assert_eq!(key.get().await.unwrap(), "gsk-first");
+        std::fs::write(&path, "gsk-second").unwrap();
+        assert_eq!(key.clone().get().await.unwrap(), "gsk-first");
+    }
+}
@@ -0,0 +1,366 @@
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::CompletionResult;
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::stream::ChatStream;
+use crate::providers::tool_call::ToolDefinition;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// On-disk response cache settings. Can be set per provider in providers.toml:
+//
+// [providers.cache]
+// ttl_secs = 3600
+// max_size_mb = 50
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    // Entries older than this are ignored and removed, None keeps them forever
+    pub ttl_secs: Option<u64>,
+    // Oldest entries are evicted once the cache grows past this size
+    pub max_size_mb: u64,
+    // Skip lookups but still store fresh responses
+    pub bypass: bool,
+    // Defaults to `cache/responses` in CONFIG_ROOT_DIR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            ttl_secs: Some(7 * 24 * 3600),
+            max_size_mb: 100,
+            bypass: false,
+            dir: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheEntry {
+    // Unix time in seconds
+    created: u64,
+    result: CompletionResult,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+    max_size: u64,
+    bypass: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig) -> Result<Self, ProviderError> {
+        let dir = match &config.dir {
+            Some(dir) => dir.clone(),
+            None => std::env::var("CONFIG_ROOT_DIR")
+                .map(|root| PathBuf::from(root).join("cache").join("responses"))
+                .map_err(|_| {
+                    ProviderError::Configuration(
+                        "CONFIG_ROOT_DIR is not set and the cache has no dir".to_string(),
+                    )
+                })?,
+        };
+        Ok(ResponseCache {
+            dir,
+            ttl: config.ttl_secs.map(Duration::from_secs),
+            max_size: config.max_size_mb * 1024 * 1024,
+            bypass: config.bypass,
+        })
+    }
+
+    // Hex encoded sha256 of the request. Key order of its objects depends on
+    // serde_json features, so callers build the request in a fixed order to
+    // keep equal requests on the same key.
+    pub fn key(request: &serde_json::Value) -> String {
+        Sha256::digest(request.to_string().as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<CompletionResult> {
+        if self.bypass {
+            return None;
+        }
+        let path = self.path(key);
+        let entry: CacheEntry = serde_json::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+        let expired = self
+            .ttl
+            .is_some_and(|ttl| now().saturating_sub(entry.created) > ttl.as_secs());
+        if expired {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.result)
+    }
+
+    // Failing to write only costs a future cache miss, so errors are ignored
+    pub fn put(&self, key: &str, result: &CompletionResult) {
+        let entry = CacheEntry {
+            created: now(),
+            result: result.clone(),
+        };
+        let Ok(content) = serde_json::to_string(&entry) else {
+            return;
+        };
+        if std::fs::create_dir_all(&self.dir).is_ok()
+            && std::fs::write(self.path(key), content).is_ok()
+        {
+            self.evict();
+        }
+    }
+
+    // Remove the oldest entries until the cache fits in max_size
+    fn evict(&self) {
+        let Ok(dir) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = dir
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            if std::fs::remove_file(path).is_ok() {
+                size -= len;
+            }
+        }
+    }
+}
+
+// ChatProvider decorator that answers repeated completion requests from the
+// response cache. Streaming and tool calls always go to the inner provider.
+#[derive(Debug)]
+pub struct CachedProvider {
+    inner: Box<dyn ChatProvider>,
+    cache: ResponseCache,
+    // Everything besides the call arguments that changes the answer: server,
+    // sampling params of the inner provider, ...
+    context: serde_json::Value,
+}
+
+impl CachedProvider {
+    pub fn new(
+        inner: Box<dyn ChatProvider>,
+        cache: ResponseCache,
+        context: serde_json::Value,
+    ) -> Self {
+        CachedProvider {
+            inner,
+            cache,
+            context,
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for CachedProvider {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let key = ResponseCache::key(&serde_json::json!({
+            "context": self.context,
+            "model": model_override.as_deref().unwrap_or(self.inner.model()),
+            "messages": messages,
+            "temperature": temperature_override,
+            "max_tokens": max_tokens_override,
+            "schema": response_schema,
+        }));
+        if let Some(result) = self.cache.get(&key) {
+            return Ok(result);
+        }
+        let result = self
+            .inner
+            .complete(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                response_schema,
+            )
+            .await?;
+        self.cache.put(&key, &result);
+        Ok(result)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        self.inner
+            .chat_completion_stream(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                response_schema,
+            )
+            .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        self.inner.chat_with_tools(messages, tools).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.list_models().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::tests::mock_provider;
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    fn cache(dir: &TempDir, config: CacheConfig) -> ResponseCache {
+        ResponseCache::new(&CacheConfig {
+            dir: Some(dir.path().to_path_buf()),
+            ..config
+        })
+        .unwrap()
+    }
+
+    fn result(content: &str) -> CompletionResult {
+        serde_json::from_value(json!({
+            "choices": [{"message": {"role": "assistant", "content": content}}]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_repeated_requests_are_served_from_cache() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "4"}}]})
+                    .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let tmp_dir = TempDir::new("cache-test").unwrap();
+        let provider = CachedProvider::new(
+            Box::new(mock_provider(&server, "phi-4")),
+            cache(&tmp_dir, CacheConfig::default()),
+            json!({}),
+        );
+
+        let ask = |question: &'static str| {
+            provider.chat_completion(
+                vec![ChatMessage::user(question)],
+                None,
+                Some(0.0),
+                None,
+                None,
+            )
+        };
+        assert_eq!(ask("2+2").await.unwrap(), "4");
+        assert_eq!(ask("2+2").await.unwrap(), "4");
+        // A different prompt is a different key
+        assert_eq!(ask("2*2").await.unwrap(), "4");
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_ttl_and_bypass() {
+        let tmp_dir = TempDir::new("cache-test").unwrap();
+        let cache = cache(
+            &tmp_dir,
+            CacheConfig {
+                ttl_secs: Some(60),
+                ..CacheConfig::default()
+            },
+        );
+        cache.put("fresh", &result("a"));
+        assert_eq!(cache.get("fresh").unwrap().content(), Some("a"));
+
+        let old = CacheEntry {
+            created: now() - 120,
+            result: result("b"),
+        };
+        std::fs::write(cache.path("old"), serde_json::to_string(&old).unwrap()).unwrap();
+        assert!(cache.get("old").is_none());
+        assert!(!cache.path("old").exists());
+
+        let bypass = ResponseCache {
+            bypass: true,
+            ..cache.clone()
+        };
+        assert!(bypass.get("fresh").is_none());
+        bypass.put("fresh", &result("c"));
+        assert_eq!(cache.get("fresh").unwrap().content(), Some("c"));
+    }
+
+    #[test]
+    fn test_evicts_oldest_entries() {
+        let tmp_dir = TempDir::new("cache-test").unwrap();
+        let mut cache = cache(&tmp_dir, CacheConfig::default());
+        cache.put("first", &result("1"));
+        let entry_size = std::fs::metadata(cache.path("first")).unwrap().len();
+        cache.max_size = entry_size * 2;
+        std::thread::sleep(Duration::from_millis(20));
+        cache.put("second", &result("2"));
+        std::thread::sleep(Duration::from_millis(20));
+        cache.put("third", &result("3"));
+
+        assert!(cache.get("first").is_none());
+        assert!(cache.get("second").is_some());
+        assert!(cache.get("third").is_some());
+    }
+
+    #[test]
+    fn test_key_is_stable() {
+        let a = ResponseCache::key(&json!({"model": "phi-4", "temperature": 0.0}));
+        let b = ResponseCache::key(&json!({"model": "phi-4", "temperature": 0.0}));
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+        assert_ne!(a, ResponseCache::key(&json!({"model": "qwen"})));
+    }
+}
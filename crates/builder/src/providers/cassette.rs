@@ -0,0 +1,380 @@
+use crate::providers::config::ProviderError;
+use crate::providers::retry::{RetryPolicy, send_with_retry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Environment variable that switches cassettes created with `from_env` to
+// record mode, e.g. `CASSETTE_MODE=record cargo test`
+pub const CASSETTE_MODE_ENV: &str = "CASSETTE_MODE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    // Send requests to the server and save every successful exchange
+    Record,
+    // Never touch the network, answer from the saved exchanges
+    Replay,
+}
+
+// Which parts of a request must be equal for a recorded response to be served
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestMatcher {
+    // Compare scheme, host and port too, not only the path and query
+    pub match_host: bool,
+    // Top level fields of a JSON body that are left out of the comparison
+    pub ignore_fields: Vec<String>,
+    // Don't compare bodies at all
+    pub ignore_body: bool,
+}
+
+impl RequestMatcher {
+    // Serve recordings made with another model
+    pub fn ignore_model(self) -> Self {
+        self.ignore_field("model")
+    }
+
+    pub fn ignore_field(mut self, field: impl Into<String>) -> Self {
+        self.ignore_fields.push(field.into());
+        self
+    }
+
+    pub fn ignore_body(mut self) -> Self {
+        self.ignore_body = true;
+        self
+    }
+
+    fn matches(&self, recorded: &RecordedRequest, request: &RecordedRequest) -> bool {
+        recorded.method == request.method
+            && self.url(&recorded.url) == self.url(&request.url)
+            && (self.ignore_body || self.body(&recorded.body) == self.body(&request.body))
+    }
+
+    fn url<'a>(&self, url: &'a str) -> &'a str {
+        if self.match_host {
+            return url;
+        }
+        // Keep the path and query only
+        url.split_once("://")
+            .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+            .unwrap_or(url)
+    }
+
+    fn body(&self, body: &Option<serde_json::Value>) -> Option<serde_json::Value> {
+        let mut body = body.clone();
+        if let Some(serde_json::Value::Object(object)) = &mut body {
+            for field in &self.ignore_fields {
+                object.remove(field);
+            }
+        }
+        body
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    // JSON bodies are kept as JSON so cassettes stay readable and diffable,
+    // anything else is stored as a string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+// Record/replay layer for provider HTTP traffic. Set it on a ModelBuilder
+// (or ProviderConfig) and every request of the built provider goes through it.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    matcher: RequestMatcher,
+    interactions: Mutex<Vec<Interaction>>,
+    // Replayed interactions are used once, so repeated identical requests get
+    // the responses in the order they were recorded
+    used: Mutex<Vec<bool>>,
+}
+
+impl Cassette {
+    // Start a new recording, overwriting `path` once the first exchange is saved
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Cassette {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            matcher: RequestMatcher::default(),
+            interactions: Mutex::new(Vec::new()),
+            used: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self, ProviderError> {
+        let path = path.into();
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            ProviderError::Configuration(format!("Cannot read cassette {}: {}", path.display(), e))
+        })?;
+        let file: CassetteFile = serde_json::from_str(&content).map_err(|e| {
+            ProviderError::Configuration(format!("Invalid cassette {}: {}", path.display(), e))
+        })?;
+        let used = vec![false; file.interactions.len()];
+        Ok(Cassette {
+            path,
+            mode: CassetteMode::Replay,
+            matcher: RequestMatcher::default(),
+            interactions: Mutex::new(file.interactions),
+            used: Mutex::new(used),
+        })
+    }
+
+    // Replay by default, record when CASSETTE_MODE=record
+    pub fn from_env(path: impl Into<PathBuf>) -> Result<Self, ProviderError> {
+        match std::env::var(CASSETTE_MODE_ENV).as_deref() {
+            Ok("record") => Ok(Self::record(path)),
+            _ => Self::replay(path),
+        }
+    }
+
+    pub fn with_matcher(mut self, matcher: RequestMatcher) -> Self {
+        self.matcher = matcher;
+        self
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Send the request built by `build` according to the cassette mode.
+    // Recording goes through `send_with_retry`, so only the final successful
+    // response of each request is saved.
+    pub async fn send<F>(
+        &self,
+        policy: &RetryPolicy,
+        build: F,
+    ) -> Result<reqwest::Response, ProviderError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let request = recorded_request(build())?;
+        match self.mode {
+            CassetteMode::Replay => {
+                let response = self.find(&request)?;
+                let response = to_response(&response)?;
+                if response.status().is_success() {
+                    Ok(response)
+                } else {
+                    Err(ProviderError::from_response(response).await)
+                }
+            }
+            CassetteMode::Record => {
+                let response = send_with_retry(policy, build).await?;
+                let status = response.status().as_u16();
+                let headers = response
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        Some((name.to_string(), value.to_str().ok()?.to_string()))
+                    })
+                    .collect();
+                let body = response.text().await.map_err(|e| {
+                    ProviderError::transport("Failed to read response for the cassette", e)
+                })?;
+                let recorded = RecordedResponse {
+                    status,
+                    headers,
+                    body,
+                };
+                self.save(Interaction {
+                    request,
+                    response: recorded.clone(),
+                })?;
+                to_response(&recorded)
+            }
+        }
+    }
+
+    fn find(&self, request: &RecordedRequest) -> Result<RecordedResponse, ProviderError> {
+        let interactions = self.interactions.lock().unwrap();
+        let mut used = self.used.lock().unwrap();
+        let index = interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| {
+                !used[i] && self.matcher.matches(&interaction.request, request)
+            })
+            .ok_or_else(|| {
+                ProviderError::ApiCall(format!(
+                    "No recorded response in cassette {} for {} {}",
+                    self.path.display(),
+                    request.method,
+                    request.url
+                ))
+            })?;
+        used[index] = true;
+        Ok(interactions[index].response.clone())
+    }
+
+    fn save(&self, interaction: Interaction) -> Result<(), ProviderError> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        let file = CassetteFile {
+            interactions: interactions.clone(),
+        };
+        let write = || -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+        };
+        write().map_err(|e| {
+            ProviderError::Configuration(format!(
+                "Cannot write cassette {}: {}",
+                self.path.display(),
+                e
+            ))
+        })
+    }
+}
+
+fn recorded_request(builder: reqwest::RequestBuilder) -> Result<RecordedRequest, ProviderError> {
+    let request = builder.build().map_err(|e| {
+        ProviderError::RequestPreparation(format!("Failed to build request: {}", e))
+    })?;
+    let body = request.body().and_then(|b| b.as_bytes()).map(|bytes| {
+        serde_json::from_slice(bytes)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(bytes).into()))
+    });
+    Ok(RecordedRequest {
+        method: request.method().to_string(),
+        url: request.url().to_string(),
+        body,
+    })
+}
+
+fn to_response(recorded: &RecordedResponse) -> Result<reqwest::Response, ProviderError> {
+    let mut builder = http::Response::builder().status(recorded.status);
+    for (name, value) in &recorded.headers {
+        builder = builder.header(name, value);
+    }
+    let response = builder.body(recorded.body.clone()).map_err(|e| {
+        ProviderError::ResponseParsing(format!("Invalid response in cassette: {}", e))
+    })?;
+    Ok(reqwest::Response::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"answer":1}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let tmp_dir = TempDir::new("cassette-test").unwrap();
+        let path = tmp_dir.path().join("chat.json");
+        let client = reqwest::Client::new();
+        let url = format!("{}/v1/chat/completions", server.url());
+        let body = serde_json::json!({"model": "phi-4", "messages": []});
+
+        let recorder = Cassette::record(&path);
+        let response = recorder
+            .send(&RetryPolicy::none(), || client.post(&url).json(&body))
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), r#"{"answer":1}"#);
+        mock.assert_async().await;
+
+        // Replay never reaches the server, the mock expects a single call
+        let player = Cassette::replay(&path)
+            .unwrap()
+            .with_matcher(RequestMatcher::default().ignore_model());
+        let other_model = serde_json::json!({"model": "qwen2.5", "messages": []});
+        let response = player
+            .send(&RetryPolicy::none(), || {
+                client
+                    .post("http://127.0.0.1:9/v1/chat/completions")
+                    .json(&other_model)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/json".to_string()
+        );
+        assert_eq!(response.text().await.unwrap(), r#"{"answer":1}"#);
+        mock.assert_async().await;
+
+        // Each recording is served once
+        let error = player
+            .send(&RetryPolicy::none(), || {
+                client.post(&url).json(&other_model)
+            })
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("No recorded response"));
+    }
+
+    #[test]
+    fn test_matcher() {
+        let request = |url: &str, model: &str| RecordedRequest {
+            method: "POST".to_string(),
+            url: url.to_string(),
+            body: Some(serde_json::json!({"model": model, "temperature": 0.1})),
+        };
+        let recorded = request("http://127.0.0.1:1234/v1/chat/completions", "phi-4");
+
+        let strict = RequestMatcher::default();
+        assert!(strict.matches(
+            &recorded,
+            &request("http://localhost:8080/v1/chat/completions", "phi-4")
+        ));
+        assert!(!strict.matches(
+            &recorded,
+            &request("http://127.0.0.1:1234/v1/chat/completions", "qwen")
+        ));
+        assert!(!strict.matches(
+            &recorded,
+            &request("http://127.0.0.1:1234/v1/models", "phi-4")
+        ));
+        assert!(RequestMatcher::default().ignore_model().matches(
+            &recorded,
+            &request("http://127.0.0.1:1234/v1/chat/completions", "qwen")
+        ));
+        let with_host = RequestMatcher {
+            match_host: true,
+            ..RequestMatcher::default()
+        };
+        assert!(!with_host.matches(
+            &recorded,
+            &request("http://localhost:8080/v1/chat/completions", "phi-4")
+        ));
+    }
+}
@@ -0,0 +1,310 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString, IntoStaticStr};
+
+// What a model accepts, used to pick request features. Known models get it
+// from the catalog below, custom ones can set `capabilities` in the config.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct ModelCapabilities {
+    // Tokens of prompt and answer together
+    pub context_window: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output: Option<u32>,
+    // Accepts a response schema natively
+    #[serde(default)]
+    pub json_schema: bool,
+    #[serde(default)]
+    pub tools: bool,
+    // Accepts images in messages
+    #[serde(default)]
+    pub vision: bool,
+    // Thinks before answering
+    #[serde(default)]
+    pub reasoning: bool,
+}
+
+// Model ids are matched by prefix, so dated and quantized variants like
+// `claude-3-5-haiku-20241022` or `qwen2.5-coder:14b` resolve too
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, EnumString, EnumIter, IntoStaticStr)]
+pub enum AnthropicModel {
+    #[strum(serialize = "claude-opus-4")]
+    ClaudeOpus4,
+    #[strum(serialize = "claude-sonnet-4")]
+    ClaudeSonnet4,
+    #[strum(serialize = "claude-3-7-sonnet")]
+    Claude37Sonnet,
+    #[strum(serialize = "claude-3-5-sonnet")]
+    Claude35Sonnet,
+    #[strum(serialize = "claude-3-5-haiku")]
+    Claude35Haiku,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, EnumString, EnumIter, IntoStaticStr)]
+pub enum MistralModel {
+    #[strum(serialize = "mistral-large")]
+    MistralLarge,
+    #[strum(serialize = "mistral-small")]
+    MistralSmall,
+    #[strum(serialize = "mistral-nemo")]
+    MistralNemo,
+    #[strum(serialize = "ministral-8b")]
+    Ministral8b,
+    #[strum(serialize = "pixtral-large")]
+    PixtralLarge,
+    #[strum(serialize = "codestral")]
+    Codestral,
+    // The open weights release, without tool support
+    #[strum(serialize = "codestral-22b")]
+    Codestral22b,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, EnumString, EnumIter, IntoStaticStr)]
+pub enum OpenAiModel {
+    #[strum(serialize = "gpt-4")]
+    Gpt4,
+    #[strum(serialize = "gpt-4-turbo")]
+    Gpt4Turbo,
+    // gpt-4-1106-preview, the first 128k release
+    #[strum(serialize = "gpt-4-1106")]
+    Gpt41106,
+    #[strum(serialize = "gpt-4o")]
+    Gpt4o,
+    #[strum(serialize = "gpt-4o-mini")]
+    Gpt4oMini,
+    #[strum(serialize = "gpt-4.1")]
+    Gpt41,
+    #[strum(serialize = "gpt-4.1-mini")]
+    Gpt41Mini,
+    #[strum(serialize = "o3")]
+    O3,
+    #[strum(serialize = "o3-mini")]
+    O3Mini,
+    #[strum(serialize = "o4-mini")]
+    O4Mini,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Display, EnumString, EnumIter, IntoStaticStr)]
+pub enum QwenModel {
+    #[strum(serialize = "qwen2.5")]
+    Qwen25,
+    #[strum(serialize = "qwen2.5-coder")]
+    Qwen25Coder,
+    // The long context release of the 7B model
+    #[strum(serialize = "qwen2.5-7b-instruct-1m")]
+    Qwen25Instruct1m,
+    #[strum(serialize = "qwen2.5-vl")]
+    Qwen25Vl,
+    #[strum(serialize = "qwq")]
+    Qwq,
+    #[strum(serialize = "qwen3")]
+    Qwen3,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KnownModel {
+    Anthropic(AnthropicModel),
+    Mistral(MistralModel),
+    OpenAi(OpenAiModel),
+    Qwen(QwenModel),
+}
+
+impl AnthropicModel {
+    pub fn capabilities(&self) -> ModelCapabilities {
+        use AnthropicModel::*;
+        let max_output = match self {
+            ClaudeOpus4 => 32_000,
+            ClaudeSonnet4 | Claude37Sonnet => 64_000,
+            Claude35Sonnet | Claude35Haiku => 8_192,
+        };
+        ModelCapabilities {
+            context_window: 200_000,
+            max_output: Some(max_output),
+            // Schemas are sent as a forced tool call
+            json_schema: true,
+            tools: true,
+            vision: true,
+            reasoning: matches!(self, ClaudeOpus4 | ClaudeSonnet4 | Claude37Sonnet),
+        }
+    }
+}
+
+impl MistralModel {
+    pub fn capabilities(&self) -> ModelCapabilities {
+        use MistralModel::*;
+        let context_window = match self {
+            Codestral => 256_000,
+            Codestral22b => 32_768,
+            _ => 128_000,
+        };
+        ModelCapabilities {
+            context_window,
+            max_output: None,
+            json_schema: *self != Codestral22b,
+            tools: *self != Codestral22b,
+            vision: matches!(self, MistralSmall | PixtralLarge),
+            reasoning: false,
+        }
+    }
+}
+
+impl OpenAiModel {
+    pub fn capabilities(&self) -> ModelCapabilities {
+        use OpenAiModel::*;
+        let (context_window, max_output) = match self {
+            Gpt4 => (8_192, 8_192),
+            Gpt4Turbo | Gpt41106 => (128_000, 4_096),
+            Gpt4o | Gpt4oMini => (128_000, 16_384),
+            Gpt41 | Gpt41Mini => (1_047_576, 32_768),
+            O3 | O3Mini | O4Mini => (200_000, 100_000),
+        };
+        ModelCapabilities {
+            context_window,
+            max_output: Some(max_output),
+            json_schema: !matches!(self, Gpt4 | Gpt4Turbo | Gpt41106),
+            tools: true,
+            vision: !matches!(self, Gpt4 | Gpt41106 | O3Mini),
+            reasoning: matches!(self, O3 | O3Mini | O4Mini),
+        }
+    }
+}
+
+impl QwenModel {
+    pub fn capabilities(&self) -> ModelCapabilities {
+        use QwenModel::*;
+        ModelCapabilities {
+            context_window: match self {
+                Qwq => 131_072,
+                Qwen25Instruct1m => 1_010_000,
+                _ => 32_768,
+            },
+            max_output: None,
+            // Local servers enforce schemas with a grammar
+            json_schema: true,
+            tools: true,
+            vision: *self == Qwen25Vl,
+            reasoning: matches!(self, Qwq | Qwen3),
+        }
+    }
+}
+
+impl KnownModel {
+    pub fn all() -> Vec<KnownModel> {
+        let mut all: Vec<KnownModel> = AnthropicModel::iter().map(KnownModel::Anthropic).collect();
+        all.extend(MistralModel::iter().map(KnownModel::Mistral));
+        all.extend(OpenAiModel::iter().map(KnownModel::OpenAi));
+        all.extend(QwenModel::iter().map(KnownModel::Qwen));
+        all
+    }
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            KnownModel::Anthropic(model) => model.into(),
+            KnownModel::Mistral(model) => model.into(),
+            KnownModel::OpenAi(model) => model.into(),
+            KnownModel::Qwen(model) => model.into(),
+        }
+    }
+
+    pub fn capabilities(&self) -> ModelCapabilities {
+        match self {
+            KnownModel::Anthropic(model) => model.capabilities(),
+            KnownModel::Mistral(model) => model.capabilities(),
+            KnownModel::OpenAi(model) => model.capabilities(),
+            KnownModel::Qwen(model) => model.capabilities(),
+        }
+    }
+
+    // Catalog entry with the longest id `model` starts with, ignoring case,
+    // the publisher prefix (`mlx-community/`) and Ollama tags (`:14b`)
+    pub fn lookup(model: &str) -> Option<KnownModel> {
+        let model = model.to_lowercase();
+        let model = model
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .replace(':', "-");
+        Self::all()
+            .into_iter()
+            .filter(|known| {
+                let id = known.id();
+                // `gpt-4` must not match `gpt-45`, only `gpt-4-...` or `gpt-4o`
+                model.starts_with(id)
+                    && model[id.len()..]
+                        .chars()
+                        .next()
+                        .is_none_or(|c| !c.is_ascii_digit() && c != '.')
+            })
+            .max_by_key(|known| known.id().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let lookup = |model| KnownModel::lookup(model);
+        assert_eq!(
+            lookup("claude-3-5-haiku-latest"),
+            Some(KnownModel::Anthropic(AnthropicModel::Claude35Haiku))
+        );
+        assert_eq!(
+            lookup("gpt-4o-mini-2024-07-18"),
+            Some(KnownModel::OpenAi(OpenAiModel::Gpt4oMini))
+        );
+        assert_eq!(
+            lookup("gpt-4.1"),
+            Some(KnownModel::OpenAi(OpenAiModel::Gpt41))
+        );
+        assert_eq!(
+            lookup("mlx-community/mistral-small-3.1-24b-instruct-2503"),
+            Some(KnownModel::Mistral(MistralModel::MistralSmall))
+        );
+        assert_eq!(
+            lookup("codestral-22b-v0.1"),
+            Some(KnownModel::Mistral(MistralModel::Codestral22b))
+        );
+        assert_eq!(
+            lookup("Qwen2.5-Coder:14b"),
+            Some(KnownModel::Qwen(QwenModel::Qwen25Coder))
+        );
+        assert_eq!(
+            lookup("gpt-4-turbo-2024-04-09"),
+            Some(KnownModel::OpenAi(OpenAiModel::Gpt4Turbo))
+        );
+        assert_eq!(
+            lookup("gpt-4-1106-preview"),
+            Some(KnownModel::OpenAi(OpenAiModel::Gpt41106))
+        );
+        assert_eq!(
+            lookup("qwen2.5-7b-instruct-1m"),
+            Some(KnownModel::Qwen(QwenModel::Qwen25Instruct1m))
+        );
+        assert_eq!(lookup("qwen3:8b"), Some(KnownModel::Qwen(QwenModel::Qwen3)));
+        assert_eq!(lookup("qwen30"), None);
+        assert_eq!(lookup("phi-4"), None);
+        assert_eq!("o3-mini".parse(), Ok(OpenAiModel::O3Mini));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let codestral = MistralModel::Codestral22b.capabilities();
+        assert!(!codestral.tools && !codestral.json_schema);
+        assert!(OpenAiModel::O4Mini.capabilities().reasoning);
+        assert!(!OpenAiModel::O3Mini.capabilities().vision);
+        assert_eq!(
+            KnownModel::Qwen(QwenModel::Qwq)
+                .capabilities()
+                .context_window,
+            131_072
+        );
+        // Ids are unique, otherwise lookups would be ambiguous
+        let mut ids: Vec<&str> = KnownModel::all().iter().map(KnownModel::id).collect();
+        let count = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::providers::completion::CompletionResult;
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::stream::ChatStream;
+use crate::providers::tool_call::ToolDefinition;
+use async_trait::async_trait;
+
+// Common interface implemented by every chat backend. `ModelBuilder::build`
+// returns it boxed, so callers don't need to know which API they talk to.
+#[async_trait]
+pub trait ChatProvider: Send + Sync + std::fmt::Debug {
+    // Model used when a request has no model override
+    fn model(&self) -> &str;
+
+    // Send the conversation and return the full result: every choice, token
+    // usage, finish reason, the model that served it and the response id
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError>;
+
+    // Send the conversation and return the text of the first answer
+    async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<String, ProviderError> {
+        self.complete(
+            messages,
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+        )
+        .await?
+        .into_content()
+    }
+
+    // Same as chat_completion, but returns the answer as a stream of deltas
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError>;
+
+    // Ask for an answer matching `schema` and parse it as JSON
+    async fn structured_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        schema: serde_json::Value,
+    ) -> Result<serde_json::Value, ProviderError> {
+        let response = self
+            .chat_completion(messages, None, None, None, Some(schema))
+            .await?;
+        serde_json::from_str(&response).map_err(|e| {
+            ProviderError::ResponseParsing(format!("Structured output is not valid JSON: {}", e))
+        })
+    }
+
+    // Offer `tools` to the model and return its reply, which either carries
+    // `tool_calls` or a plain text answer
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError>;
+
+    // Ids of the models the server currently offers
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError>;
+}
@@ -0,0 +1,144 @@
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use serde::{Deserialize, Serialize};
+
+// Token counts reported by the server for one call. Embedding responses
+// have no completion tokens.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompletionChoice {
+    #[serde(default)]
+    pub index: usize,
+    pub message: ChatMessage,
+    pub finish_reason: Option<String>,
+}
+
+// Everything a chat completion returned, in the OpenAI response layout
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompletionResult {
+    #[serde(default)]
+    pub id: String,
+    // Model that actually served the request
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub created: Option<u64>,
+    pub choices: Vec<CompletionChoice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+impl CompletionResult {
+    pub fn first_choice(&self) -> Option<&CompletionChoice> {
+        self.choices.first()
+    }
+
+    // Text of the first choice
+    pub fn content(&self) -> Option<&str> {
+        self.first_choice()
+            .and_then(|c| c.message.content.as_text())
+    }
+
+    // Thinking of the first choice, when the model is a reasoning model
+    pub fn reasoning(&self) -> Option<&str> {
+        self.first_choice()
+            .and_then(|c| c.message.reasoning.as_deref())
+    }
+
+    // Move the leading `<think>` block of every choice out of the answer
+    pub fn split_reasoning(&mut self, reasoning_model: bool) {
+        self.choices
+            .iter_mut()
+            .for_each(|choice| choice.message.split_reasoning(reasoning_model));
+    }
+
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.first_choice().and_then(|c| c.finish_reason.as_deref())
+    }
+
+    // True when the answer was cut off by the max_tokens limit
+    // (`length` for OpenAI compatible servers, `max_tokens` for Anthropic)
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.finish_reason(), Some("length") | Some("max_tokens"))
+    }
+
+    pub fn into_message(self) -> Result<ChatMessage, ProviderError> {
+        self.choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or_else(|| ProviderError::ResponseParsing("No completions returned".to_string()))
+    }
+
+    pub fn into_content(self) -> Result<String, ProviderError> {
+        self.into_message()
+            .map(|message| message.content.into_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_openai_response() {
+        let result: CompletionResult = serde_json::from_str(
+            r#"{
+                "id": "chatcmpl-123",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "qwen2.5-7b-instruct-1m",
+                "choices": [
+                    {"index": 0, "message": {"role": "assistant", "content": "fn main"}, "finish_reason": "length"},
+                    {"index": 1, "message": {"role": "assistant", "content": "fn"}, "finish_reason": "length"}
+                ],
+                "usage": {"prompt_tokens": 12, "completion_tokens": 2, "total_tokens": 14}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(result.id, "chatcmpl-123");
+        assert_eq!(result.model, "qwen2.5-7b-instruct-1m");
+        assert_eq!(result.choices.len(), 2);
+        assert_eq!(result.usage.unwrap().total_tokens, 14);
+        assert!(result.is_truncated());
+        assert_eq!(result.into_content().unwrap(), "fn main");
+    }
+
+    #[test]
+    fn test_reasoning() {
+        let mut result: CompletionResult = serde_json::from_str(
+            r#"{"choices": [
+                {"message": {"role": "assistant", "content": "42", "reasoning_content": "6 * 7"}},
+                {"message": {"role": "assistant", "content": "<think>6 * 7</think>\n\n42"}}
+            ]}"#,
+        )
+        .unwrap();
+        result.split_reasoning(false);
+        for choice in &result.choices {
+            assert_eq!(choice.message.content, "42");
+            assert_eq!(choice.message.reasoning.as_deref(), Some("6 * 7"));
+        }
+        assert_eq!(result.reasoning(), Some("6 * 7"));
+
+        let message = serde_json::to_value(result.into_message().unwrap().without_reasoning());
+        assert_eq!(
+            message.unwrap(),
+            serde_json::json!({"role": "assistant", "content": "42"})
+        );
+    }
+
+    #[test]
+    fn test_empty_choices() {
+        let result: CompletionResult = serde_json::from_str(r#"{"choices": []}"#).unwrap();
+        assert!(result.content().is_none());
+        assert!(!result.is_truncated());
+        assert!(result.into_content().is_err());
+    }
+}
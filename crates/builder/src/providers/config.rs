@@ -1,6 +1,18 @@
-use super::provider::ApiType;
+use super::api_key::ApiKey;
+use super::cassette::Cassette;
+use super::http::HttpConfig;
+use super::openai::ChatMessage;
+use super::provider::{ApiType, ProviderType};
+use super::retry::{RetryPolicy, parse_retry_after, send_with_retry};
+use super::stream::{ChatDelta, ChatStream};
+use super::structured::StructuredAttempt;
+use futures::StreamExt;
 use std::error::Error;
 use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Clone)]
 pub enum ProviderError {
@@ -8,6 +20,27 @@ pub enum ProviderError {
     RequestPreparation(String),
     ApiCall(String),
     ResponseParsing(String),
+    // The server answered with a non-success status code
+    Http {
+        status: u16,
+        // Human readable message taken from the provider's error body
+        message: String,
+        // Raw error body as returned by the provider
+        body: String,
+        // Delay requested by the server with the `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+    // The request never got a response (connection refused, timeout, ...)
+    Transport {
+        message: String,
+        source: Arc<reqwest::Error>,
+    },
+    // Every answer to a `chat_structured` call broke the schema
+    Structured {
+        attempts: Vec<StructuredAttempt>,
+    },
+    // The caller cancelled the request through its CancellationToken
+    Cancelled,
 }
 
 impl fmt::Display for ProviderError {
@@ -19,39 +52,213 @@ impl fmt::Display for ProviderError {
             }
             ProviderError::ApiCall(msg) => write!(f, "API call error: {}", msg),
             ProviderError::ResponseParsing(msg) => write!(f, "Response parsing error: {}", msg),
+            ProviderError::Http {
+                status, message, ..
+            } => write!(f, "API returned status code {}: {}", status, message),
+            ProviderError::Transport { message, source } => {
+                write!(f, "{}: {}", message, source)
+            }
+            ProviderError::Structured { attempts } => write!(
+                f,
+                "Structured output still invalid after {} attempts: {}",
+                attempts.len(),
+                attempts
+                    .last()
+                    .map(|attempt| attempt.errors.join("; "))
+                    .unwrap_or_default()
+            ),
+            ProviderError::Cancelled => write!(f, "Request was cancelled"),
+        }
+    }
+}
+
+impl Error for ProviderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProviderError::Transport { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ProviderError {
+    // Build an Http error from a non-success response, reading its body
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response
+            .text()
+            .await
+            .unwrap_or_else(|_| "<could not read error response>".to_string());
+        ProviderError::Http {
+            status,
+            message: error_message(&body),
+            body,
+            retry_after,
+        }
+    }
+
+    pub fn transport(message: impl Into<String>, source: reqwest::Error) -> Self {
+        ProviderError::Transport {
+            message: message.into(),
+            source: Arc::new(source),
+        }
+    }
+
+    // HTTP status code of the failed call, if the server answered at all
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ProviderError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProviderError::Http { retry_after, .. } => *retry_after,
+            _ => None,
         }
     }
+
+    // Whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::Http { status, .. } => {
+                matches!(status, 408 | 409 | 425 | 429 | 500 | 502 | 503 | 504 | 529)
+            }
+            // Other transport errors come from building the request (bad
+            // URL, body) and fail the same way every time
+            ProviderError::Transport { source, .. } => source.is_timeout() || source.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+fn until_cancelled(
+    stream: ChatStream,
+    cancel: CancellationToken,
+) -> impl futures::Stream<Item = Result<ChatDelta, ProviderError>> {
+    futures::stream::unfold(Some(stream), move |stream| {
+        let cancel = cancel.clone();
+        async move {
+            let mut stream = stream?;
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Some((Err(ProviderError::Cancelled), None)),
+                delta = stream.next() => delta.map(|delta| (delta, Some(stream))),
+            }
+        }
+    })
 }
 
-impl Error for ProviderError {}
+// OpenAI and Anthropic both send `{"error": {"type": ..., "message": ...}}`,
+// fall back to the raw body for anything else
+fn error_message(body: &str) -> String {
+    let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    let error = parsed.as_ref().and_then(|v| v.get("error"));
+    let message = error
+        .and_then(|e| e.get("message").or(Some(e)))
+        .and_then(|m| m.as_str());
+    let kind = error.and_then(|e| e.get("type")).and_then(|t| t.as_str());
+    match (kind, message) {
+        (Some(kind), Some(message)) => format!("{}: {}", kind, message),
+        (None, Some(message)) => message.to_string(),
+        _ => body.to_string(),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ProviderConfig {
     pub api_type: ApiType,
-    pub api_key: Option<String>,
+    // Resolved by the provider on its first request
+    pub api_key: Option<ApiKey>,
     pub api_base_url: Option<String>,
     pub model: String,
+    // Backend behind the API, decides which sampling parameters are accepted
+    pub provider_type: ProviderType,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u16>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    // Context window size, only backends that can resize it per request accept it
+    pub ctx_size: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    // Number of choices to generate
+    pub n: Option<u32>,
+    pub seed: Option<i64>,
+    pub repeat_penalty: Option<f32>,
+    // Ollama only: mirostat sampling mode (0 off, 1 or 2) and its settings
+    pub mirostat: Option<u8>,
+    pub mirostat_tau: Option<f32>,
+    pub mirostat_eta: Option<f32>,
+    // Ollama only: how long the model stays loaded after a request, e.g. "10m"
+    pub keep_alive: Option<String>,
     pub schema: Option<String>,
+    // Texts per request when embedding, see embedding::DEFAULT_BATCH_SIZE
+    pub embedding_batch_size: Option<usize>,
+    pub retry: RetryPolicy,
+    // Record/replay layer for the HTTP traffic, used by tests
+    pub cassette: Option<Arc<Cassette>>,
+    // Timeouts, proxy, headers and extra body fields
+    pub http: HttpConfig,
+    // Aborts requests and streams in flight when cancelled
+    pub cancel: Option<CancellationToken>,
+    // Reasoning model, its answer may continue a think block the chat
+    // template opened in the prompt
+    pub reasoning: bool,
+    // Send the reasoning of earlier answers back. DeepSeek rejects it, but
+    // some APIs want it during tool call loops.
+    pub keep_reasoning: bool,
     // Add other config options as needed
 }
 
 impl ProviderConfig {
     pub fn new(api_type: ApiType, model: String) -> Self {
+        let provider_type = match api_type {
+            ApiType::Anthropic => ProviderType::Anthropic,
+            ApiType::OpenAI => ProviderType::OpenAI,
+            ApiType::Ollama => ProviderType::Ollama,
+        };
         ProviderConfig {
             api_type,
             api_key: None,
             api_base_url: None,
             model,
+            provider_type,
             temperature: None,
             max_tokens: None,
+            top_p: None,
+            top_k: None,
+            ctx_size: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            n: None,
+            seed: None,
+            repeat_penalty: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            keep_alive: None,
             schema: None,
+            embedding_batch_size: None,
+            retry: RetryPolicy::default(),
+            cassette: None,
+            http: HttpConfig::default(),
+            cancel: None,
+            reasoning: false,
+            keep_reasoning: false,
         }
     }
 
-    pub fn with_api_key(mut self, api_key: String) -> Self {
-        self.api_key = Some(api_key);
+    pub fn with_api_key(mut self, api_key: impl Into<ApiKey>) -> Self {
+        self.api_key = Some(api_key.into());
         self
     }
 
@@ -69,8 +276,426 @@ impl ProviderConfig {
         self.max_tokens = Some(max_tokens);
         self
     }
+
+    pub fn with_provider_type(mut self, provider_type: ProviderType) -> Self {
+        self.provider_type = provider_type;
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_ctx_size(mut self, ctx_size: u32) -> Self {
+        self.ctx_size = Some(ctx_size);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    pub fn with_mirostat(mut self, mirostat: u8) -> Self {
+        self.mirostat = Some(mirostat);
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: String) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
     pub fn with_schema(mut self, schema: String) -> Self {
         self.schema = Some(schema);
         self
     }
+
+    pub fn with_embedding_batch_size(mut self, batch_size: usize) -> Self {
+        self.embedding_batch_size = Some(batch_size);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub fn with_http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_reasoning(mut self, reasoning: bool) -> Self {
+        self.reasoning = reasoning;
+        self
+    }
+
+    pub fn with_keep_reasoning(mut self, keep: bool) -> Self {
+        self.keep_reasoning = keep;
+        self
+    }
+
+    // Messages as they go into a request body, without the reasoning of
+    // earlier answers unless `keep_reasoning` is set
+    pub fn outgoing_messages(&self, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+        if self.keep_reasoning {
+            return messages;
+        }
+        messages
+            .into_iter()
+            .map(ChatMessage::without_reasoning)
+            .collect()
+    }
+
+    // Send a request with the retry policy, through the cassette if one is set.
+    // Cancelling the token drops the request, retries included.
+    pub async fn send<F>(&self, build: F) -> Result<reqwest::Response, ProviderError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let send = async {
+            match &self.cassette {
+                Some(cassette) => cassette.send(&self.retry, build).await,
+                None => send_with_retry(&self.retry, build).await,
+            }
+        };
+        self.unless_cancelled(send).await?
+    }
+
+    // Await `future`, e.g. reading the body of a response, or give up with
+    // ProviderError::Cancelled when the token is cancelled first
+    pub async fn unless_cancelled<T>(
+        &self,
+        future: impl Future<Output = T>,
+    ) -> Result<T, ProviderError> {
+        match &self.cancel {
+            Some(cancel) => tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Err(ProviderError::Cancelled),
+                output = future => Ok(output),
+            },
+            None => Ok(future.await),
+        }
+    }
+
+    // End `stream` with ProviderError::Cancelled once the token is cancelled
+    pub fn cancellable(&self, stream: ChatStream) -> ChatStream {
+        match &self.cancel {
+            Some(cancel) => Box::pin(until_cancelled(stream, cancel.clone())),
+            None => stream,
+        }
+    }
+
+    // Settings that change the answer for a given conversation, used to key
+    // the response cache
+    pub fn cache_context(&self) -> serde_json::Value {
+        serde_json::json!({
+            "api_type": self.api_type.to_string(),
+            "api_base_url": self.api_base_url,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+            "top_p": self.top_p,
+            "top_k": self.top_k,
+            "ctx_size": self.ctx_size,
+            "stop": self.stop,
+            "presence_penalty": self.presence_penalty,
+            "frequency_penalty": self.frequency_penalty,
+            "n": self.n,
+            "seed": self.seed,
+            "repeat_penalty": self.repeat_penalty,
+            "mirostat": self.mirostat,
+            "mirostat_tau": self.mirostat_tau,
+            "mirostat_eta": self.mirostat_eta,
+            "extra_body": self.http.extra_body,
+        })
+    }
+
+    // Check that the backend accepts every sampling parameter that is set,
+    // so nothing gets silently dropped from the requests
+    pub fn validate(&self) -> Result<(), ProviderError> {
+        let unsupported = self.unsupported_params();
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+        Err(ProviderError::Configuration(format!(
+            "{} ({} API) does not support: {}",
+            self.provider_type,
+            self.api_type,
+            unsupported.join(", ")
+        )))
+    }
+
+    fn unsupported_params(&self) -> Vec<&'static str> {
+        let mut unsupported = Vec::new();
+        // Parameters only Ollama's native API knows about
+        if self.api_type != ApiType::Ollama {
+            if self.mirostat.is_some() || self.mirostat_tau.is_some() || self.mirostat_eta.is_some()
+            {
+                unsupported.push("mirostat");
+            }
+            if self.keep_alive.is_some() {
+                unsupported.push("keep_alive");
+            }
+        }
+        match self.api_type {
+            ApiType::Anthropic => {
+                // The Messages API supports temperature, top_p, top_k and stop
+                // sequences, and always returns a single choice
+                if self.ctx_size.is_some() {
+                    unsupported.push("ctx");
+                }
+                if self.presence_penalty.is_some() {
+                    unsupported.push("presence_penalty");
+                }
+                if self.frequency_penalty.is_some() {
+                    unsupported.push("frequency_penalty");
+                }
+                if self.repeat_penalty.is_some() {
+                    unsupported.push("repeat_penalty");
+                }
+                if self.n.is_some_and(|n| n != 1) {
+                    unsupported.push("n");
+                }
+                if self.seed.is_some() {
+                    unsupported.push("seed");
+                }
+            }
+            ApiType::OpenAI => {
+                // The context size is fixed when the model is loaded, the
+                // OpenAI compatible endpoints can't change it per request
+                if self.ctx_size.is_some() {
+                    unsupported.push("ctx");
+                }
+                // top_k and repeat_penalty are extensions only llama.cpp based
+                // servers accept
+                let llama_cpp = matches!(
+                    self.provider_type,
+                    ProviderType::LmStudio | ProviderType::Llamafile
+                );
+                if self.top_k.is_some() && !llama_cpp {
+                    unsupported.push("top_k");
+                }
+                if self.repeat_penalty.is_some() && !llama_cpp {
+                    unsupported.push("repeat_penalty");
+                }
+                self.hosted_quirks(&mut unsupported);
+            }
+            ApiType::Ollama => {
+                // Every sampling option maps to `options`, but there is no way
+                // to ask for more than one choice
+                if self.n.is_some_and(|n| n != 1) {
+                    unsupported.push("n");
+                }
+            }
+        }
+        unsupported
+    }
+
+    // What the hosted OpenAI compatible APIs reject or silently ignore
+    fn hosted_quirks(&self, unsupported: &mut Vec<&'static str>) {
+        let n = (self.n.is_some_and(|n| n != 1), "n");
+        let penalties = [
+            (self.presence_penalty.is_some(), "presence_penalty"),
+            (self.frequency_penalty.is_some(), "frequency_penalty"),
+        ];
+        let mut rejected = Vec::new();
+        match self.provider_type {
+            ProviderType::Groq => rejected.push(n),
+            ProviderType::Cerebras => rejected.extend(penalties),
+            ProviderType::DeepSeek => {
+                rejected.push(n);
+                // The reasoner fixes its own sampling and ignores these
+                if self.model.starts_with("deepseek-reasoner") {
+                    rejected.push((self.temperature.is_some(), "temperature"));
+                    rejected.push((self.top_p.is_some(), "top_p"));
+                    rejected.extend(penalties);
+                }
+            }
+            // Grok reasoning models reject penalties and stop sequences
+            ProviderType::XAI
+                if self.model.starts_with("grok-3-mini") || self.model.starts_with("grok-4") =>
+            {
+                rejected.extend(penalties);
+                rejected.push((self.stop.is_some(), "stop"));
+            }
+            _ => {}
+        }
+        unsupported.extend(
+            rejected
+                .into_iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, name)| name),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error_message(
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#
+            ),
+            "overloaded_error: Overloaded"
+        );
+        assert_eq!(
+            error_message(r#"{"error":{"message":"model not loaded","code":null}}"#),
+            "model not loaded"
+        );
+        assert_eq!(
+            error_message(r#"{"error":"Unexpected endpoint"}"#),
+            "Unexpected endpoint"
+        );
+        assert_eq!(error_message("Bad Gateway"), "Bad Gateway");
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let http = |status| ProviderError::Http {
+            status,
+            message: String::new(),
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(http(429).is_retryable());
+        assert!(http(503).is_retryable());
+        assert!(!http(400).is_retryable());
+        assert!(!http(401).is_retryable());
+        assert!(!ProviderError::ResponseParsing("bad json".to_string()).is_retryable());
+        let bad_url = reqwest::Client::new()
+            .get("http://[::1")
+            .build()
+            .unwrap_err();
+        assert!(!ProviderError::transport("Request failed", bad_url).is_retryable());
+    }
+
+    #[test]
+    fn test_validate_sampling_params() {
+        let openai = ProviderConfig::new(ApiType::OpenAI, "gpt4".to_string())
+            .with_top_p(0.9)
+            .with_seed(42)
+            .with_n(2);
+        assert!(openai.validate().is_ok());
+
+        let error = openai.clone().with_top_k(40).with_ctx_size(8192).validate();
+        match error {
+            Err(ProviderError::Configuration(message)) => {
+                assert_eq!(message, "OpenAI (OpenAI API) does not support: ctx, top_k")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let lm_studio = openai
+            .with_provider_type(ProviderType::LmStudio)
+            .with_top_k(40);
+        assert!(lm_studio.validate().is_ok());
+
+        let anthropic = ProviderConfig::new(ApiType::Anthropic, "claude".to_string())
+            .with_top_k(40)
+            .with_stop(vec!["END".to_string()]);
+        assert!(anthropic.validate().is_ok());
+        assert!(anthropic.with_seed(1).validate().is_err());
+
+        let groq = ProviderConfig::new(ApiType::OpenAI, "llama-3.1-8b-instant".to_string())
+            .with_provider_type(ProviderType::Groq)
+            .with_n(2);
+        assert!(
+            groq.validate()
+                .unwrap_err()
+                .to_string()
+                .contains("support: n")
+        );
+
+        let deepseek = ProviderConfig::new(ApiType::OpenAI, "deepseek-reasoner".to_string())
+            .with_provider_type(ProviderType::DeepSeek)
+            .with_temperature(0.2);
+        assert!(deepseek.validate().is_err());
+        let deepseek = ProviderConfig {
+            model: "deepseek-chat".to_string(),
+            ..deepseek
+        };
+        assert!(deepseek.validate().is_ok());
+
+        let grok = ProviderConfig::new(ApiType::OpenAI, "grok-4".to_string())
+            .with_provider_type(ProviderType::XAI)
+            .with_stop(vec!["END".to_string()]);
+        assert!(grok.validate().is_err());
+
+        let ollama = ProviderConfig::new(ApiType::Ollama, "granite3.2".to_string())
+            .with_ctx_size(8192)
+            .with_top_k(40)
+            .with_mirostat(2)
+            .with_keep_alive("10m".to_string());
+        assert!(ollama.validate().is_ok());
+        assert!(ollama.with_n(2).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancellable_stream() {
+        let cancel = CancellationToken::new();
+        let config = ProviderConfig::new(ApiType::OpenAI, "gpt4".to_string())
+            .with_cancellation(cancel.clone());
+        let delta = ChatDelta {
+            content: "Hello".to_string(),
+            ..Default::default()
+        };
+        let deltas = futures::stream::iter([Ok(delta)]).chain(futures::stream::pending());
+        let mut stream = config.cancellable(Box::pin(deltas));
+        assert_eq!(stream.next().await.unwrap().unwrap().content, "Hello");
+        cancel.cancel();
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(ProviderError::Cancelled))
+        ));
+        assert!(stream.next().await.is_none());
+
+        // A body that never finishes arriving
+        let body = config.unless_cancelled(std::future::pending::<()>());
+        assert!(matches!(body.await, Err(ProviderError::Cancelled)));
+    }
 }
@@ -0,0 +1,233 @@
+use crate::providers::config::ProviderError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Content of a chat message, either plain text or a list of parts mixing
+// text and images. Serializes like the OpenAI API: a string or an array.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    // http(s) URL or a `data:<media type>;base64,<data>` URL
+    pub url: String,
+    // OpenAI only: "low", "high" or "auto"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+impl MessageContent {
+    // The text of a text only message
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(text) => Some(text),
+            MessageContent::Parts(parts) => match parts.as_slice() {
+                [ContentPart::Text { text }] => Some(text),
+                _ => None,
+            },
+        }
+    }
+
+    // All text parts joined by newlines, images are left out
+    pub fn to_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn into_text(self) -> String {
+        match self {
+            MessageContent::Text(text) => text,
+            parts => parts.to_text(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.is_empty(),
+            MessageContent::Parts(parts) => parts.is_empty(),
+        }
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &ImageUrl> {
+        let parts = match self {
+            MessageContent::Text(_) => &[][..],
+            MessageContent::Parts(parts) => parts.as_slice(),
+        };
+        parts.iter().filter_map(|part| match part {
+            ContentPart::ImageUrl { image_url } => Some(image_url),
+            ContentPart::Text { .. } => None,
+        })
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&String> for MessageContent {
+    fn from(text: &String) -> Self {
+        MessageContent::Text(text.clone())
+    }
+}
+
+impl From<Vec<ContentPart>> for MessageContent {
+    fn from(parts: Vec<ContentPart>) -> Self {
+        MessageContent::Parts(parts)
+    }
+}
+
+impl PartialEq<str> for MessageContent {
+    fn eq(&self, other: &str) -> bool {
+        self.as_text() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for MessageContent {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_text() == Some(*other)
+    }
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: url.into(),
+                detail: None,
+            },
+        }
+    }
+
+    // Inline image, sent base64 encoded in a data URL
+    pub fn image_bytes(media_type: &str, bytes: &[u8]) -> Self {
+        Self::image_url(format!(
+            "data:{};base64,{}",
+            media_type,
+            STANDARD.encode(bytes)
+        ))
+    }
+
+    // Read a local png, jpeg, gif or webp image
+    pub fn image_file(path: impl AsRef<Path>) -> Result<Self, ProviderError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let media_type = match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            _ => {
+                return Err(ProviderError::RequestPreparation(format!(
+                    "Unsupported image type: {}",
+                    path.display()
+                )));
+            }
+        };
+        let bytes = std::fs::read(path).map_err(|e| {
+            ProviderError::RequestPreparation(format!(
+                "Cannot read image {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(Self::image_bytes(media_type, &bytes))
+    }
+}
+
+impl ImageUrl {
+    // Media type and base64 data of a `data:` URL
+    pub fn as_base64(&self) -> Option<(&str, &str)> {
+        let rest = self.url.strip_prefix("data:")?;
+        let (media_type, data) = rest.split_once(',')?;
+        Some((media_type.strip_suffix(";base64")?, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_serialize_like_openai() {
+        let content = MessageContent::from(vec![
+            ContentPart::text("What is on this screenshot?"),
+            ContentPart::image_url("https://example.com/ui.png"),
+        ]);
+        assert_eq!(
+            serde_json::to_value(&content).unwrap(),
+            json!([
+                {"type": "text", "text": "What is on this screenshot?"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/ui.png"}}
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(MessageContent::from("hi")).unwrap(),
+            json!("hi")
+        );
+        let parsed: MessageContent =
+            serde_json::from_value(json!([{"type": "text", "text": "hi"}])).unwrap();
+        assert_eq!(parsed, "hi");
+        assert_eq!(content.to_text(), "What is on this screenshot?");
+        assert!(content.as_text().is_none());
+    }
+
+    #[test]
+    fn test_image_file() {
+        let tmp_dir = TempDir::new("content-test").unwrap();
+        let path = tmp_dir.path().join("diagram.PNG");
+        std::fs::write(&path, b"png").unwrap();
+        let ContentPart::ImageUrl { image_url } = ContentPart::image_file(&path).unwrap() else {
+            panic!("expected an image");
+        };
+        assert_eq!(image_url.url, "data:image/png;base64,cG5n");
+        assert_eq!(image_url.as_base64(), Some(("image/png", "cG5n")));
+
+        assert!(ContentPart::image_file(tmp_dir.path().join("notes.txt")).is_err());
+        assert!(ContentPart::image_file(tmp_dir.path().join("missing.jpg")).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::provider::ModelBuilder;
+use crate::providers::tokenizer::{CharEstimator, Tokenizer};
+use crate::providers::tool_call::ToolDefinition;
+use std::ops::Range;
+use std::sync::Arc;
+
+// Used when neither the model config nor the catalog knows the window
+pub const DEFAULT_CONTEXT_WINDOW: usize = 4096;
+// Room left for the answer when the model has no `max_tokens`
+pub const DEFAULT_OUTPUT_RESERVE: usize = 1024;
+// Role and chat template markers around every message
+const MESSAGE_OVERHEAD: usize = 4;
+// Providers scale images down to roughly this many tokens
+const IMAGE_TOKENS: usize = 768;
+
+// What to drop when the history no longer fits. System messages at the
+// start are always kept, and turns (a user message with everything answering
+// it) are dropped whole so tool calls never lose their results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncationPolicy {
+    // Drop the oldest turns until the rest fits
+    #[default]
+    DropOldest,
+    // Keep at most the last N turns, fewer if they still don't fit
+    KeepLastTurns(usize),
+    // Never drop anything, fail when the history doesn't fit
+    Fail,
+}
+
+// Chat history that knows how many tokens it takes and trims itself to the
+// model's context window before each request
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    messages: Vec<ChatMessage>,
+    tokenizer: Arc<dyn Tokenizer>,
+    context_window: usize,
+    reserved_output: usize,
+    // Prompt tokens outside the messages, e.g. a system prompt added by a
+    // provider decorator
+    reserved_prompt: usize,
+    // Tool definitions and response schema sent along with the messages, as
+    // JSON
+    request_extras: Vec<String>,
+    policy: TruncationPolicy,
+    // Send the thinking of reasoning models back, `messages` always has it
+    keep_reasoning: bool,
+}
+
+impl Conversation {
+    pub fn new(context_window: usize) -> Self {
+        Conversation {
+            messages: Vec::new(),
+            tokenizer: Arc::new(CharEstimator::default()),
+            context_window,
+            reserved_output: DEFAULT_OUTPUT_RESERVE,
+            reserved_prompt: 0,
+            request_extras: Vec::new(),
+            policy: TruncationPolicy::default(),
+            keep_reasoning: false,
+        }
+    }
+
+    // Budget taken from the model's context window and `max_tokens`
+    pub fn for_model(model: &ModelBuilder) -> Self {
+        let context_window = model
+            .context_window()
+            .map_or(DEFAULT_CONTEXT_WINDOW, |w| w as usize);
+        let reserved_output = model
+            .max_tokens()
+            .map_or(DEFAULT_OUTPUT_RESERVE, usize::from);
+        Self::new(context_window).with_reserved_output(reserved_output)
+    }
+
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.tokenizer = Arc::new(tokenizer);
+        self
+    }
+
+    pub fn with_policy(mut self, policy: TruncationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_reserved_output(mut self, tokens: usize) -> Self {
+        self.reserved_output = tokens;
+        self
+    }
+
+    pub fn with_reserved_prompt(mut self, tokens: usize) -> Self {
+        self.reserved_prompt = tokens;
+        self
+    }
+
+    // Tools offered with every request, their definitions take prompt tokens
+    pub fn with_tools(mut self, tools: &[ToolDefinition]) -> Self {
+        self.request_extras.extend(
+            tools
+                .iter()
+                .filter_map(|tool| serde_json::to_string(tool).ok()),
+        );
+        self
+    }
+
+    // Schema sent with every request, as response format or in the prompt
+    pub fn with_response_schema(mut self, schema: &serde_json::Value) -> Self {
+        self.request_extras.push(schema.to_string());
+        self
+    }
+
+    // Some APIs want the reasoning back during tool call loops, most
+    // only waste context on it. The provider drops it too unless it was
+    // built with ModelBuilder::with_keep_reasoning.
+    pub fn with_reasoning(mut self, keep: bool) -> Self {
+        self.keep_reasoning = keep;
+        self
+    }
+
+    pub fn push(&mut self, message: ChatMessage) -> &mut Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn extend(&mut self, messages: impl IntoIterator<Item = ChatMessage>) -> &mut Self {
+        self.messages.extend(messages);
+        self
+    }
+
+    // The full history, including messages that no longer fit and the
+    // reasoning that is not sent
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    // Tokens the messages may take, what is left after the reserved output,
+    // the reserved prompt and the tools and schema
+    pub fn budget(&self) -> usize {
+        let extras: usize = self
+            .request_extras
+            .iter()
+            .map(|extra| self.tokenizer.count(extra))
+            .sum();
+        self.context_window
+            .saturating_sub(self.reserved_output)
+            .saturating_sub(self.reserved_prompt)
+            .saturating_sub(extras)
+    }
+
+    pub fn message_tokens(&self, message: &ChatMessage) -> usize {
+        let tool_calls: usize = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| {
+                self.tokenizer.count(&call.function.name)
+                    + self.tokenizer.count(&call.function.arguments)
+            })
+            .sum();
+        let reasoning = match &message.reasoning {
+            Some(reasoning) if self.keep_reasoning => self.tokenizer.count(reasoning),
+            _ => 0,
+        };
+        MESSAGE_OVERHEAD
+            + reasoning
+            + self.tokenizer.count(&message.content.to_text())
+            + message.content.images().count() * IMAGE_TOKENS
+            + tool_calls
+    }
+
+    // Estimated size of the whole history
+    pub fn tokens(&self) -> usize {
+        self.count(&self.messages)
+    }
+
+    fn count(&self, messages: &[ChatMessage]) -> usize {
+        messages.iter().map(|m| self.message_tokens(m)).sum()
+    }
+
+    // Number of leading system messages and the index of every turn start
+    fn turns(&self) -> (usize, Vec<usize>) {
+        let system_len = self
+            .messages
+            .iter()
+            .position(|m| m.role != "system")
+            .unwrap_or(self.messages.len());
+        // Every user message starts a new turn
+        let turns = (system_len..self.messages.len())
+            .filter(|&i| i == system_len || self.messages[i].role == "user")
+            .collect();
+        (system_len, turns)
+    }
+
+    // Messages between the leading system messages and the last
+    // `keep_last_turns` turns, the part a summary can replace
+    pub fn compactable(&self, keep_last_turns: usize) -> Range<usize> {
+        let (system_len, turns) = self.turns();
+        let end = turns
+            .len()
+            .checked_sub(keep_last_turns.max(1))
+            .map_or(system_len, |kept| turns[kept]);
+        system_len..end
+    }
+
+    // Replace `range` of the history, e.g. with a summary of it
+    pub fn splice(&mut self, range: Range<usize>, messages: impl IntoIterator<Item = ChatMessage>) {
+        self.messages.splice(range, messages);
+    }
+
+    // Messages to send: the history trimmed by the policy to fit the budget
+    pub fn request_messages(&self) -> Result<Vec<ChatMessage>, ProviderError> {
+        let (system_len, turns) = self.turns();
+        let (system, rest) = self.messages.split_at(system_len);
+        let mut turns: Vec<usize> = turns.into_iter().map(|i| i - system_len).collect();
+        if let TruncationPolicy::KeepLastTurns(n) = self.policy {
+            turns.drain(..turns.len().saturating_sub(n.max(1)));
+        }
+
+        let budget = self.budget();
+        let fixed = self.count(system);
+        let mut start = turns.first().copied().unwrap_or(rest.len());
+        let mut tokens = fixed + self.count(&rest[start..]);
+        if self.policy != TruncationPolicy::Fail {
+            // The last turn is never dropped, the request makes no sense without it
+            for &next in turns.iter().skip(1) {
+                if tokens <= budget {
+                    break;
+                }
+                tokens -= self.count(&rest[start..next]);
+                start = next;
+            }
+        }
+        if tokens > budget {
+            return Err(ProviderError::RequestPreparation(format!(
+                "Conversation needs about {} tokens, only {} fit the context window of {}",
+                tokens, budget, self.context_window
+            )));
+        }
+        Ok(system
+            .iter()
+            .chain(&rest[start..])
+            .cloned()
+            .map(|m| {
+                if self.keep_reasoning {
+                    m
+                } else {
+                    m.without_reasoning()
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::provider::{Provider, ProviderType};
+    use crate::providers::tool_call::ToolCall;
+
+    // One token per character keeps the numbers easy to follow
+    fn conversation(context_window: usize) -> Conversation {
+        let mut conversation = Conversation::new(context_window)
+            .with_reserved_output(0)
+            .with_tokenizer(CharEstimator {
+                chars_per_token: 1.0,
+            });
+        let mut call = ChatMessage::assistant("");
+        call.tool_calls = Some(vec![ToolCall::new("1", "read", "{}".to_string())]);
+        conversation.extend([
+            ChatMessage::system("rules"),
+            ChatMessage::user("first"),
+            ChatMessage::assistant("one"),
+            ChatMessage::user("second"),
+            call,
+            ChatMessage::tool("1", "text"),
+            ChatMessage::user("third"),
+        ]);
+        conversation
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<String> {
+        messages.iter().map(|m| m.content.to_text()).collect()
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let conversation = conversation(1000);
+        assert_eq!(conversation.tokens(), 7 * 4 + 5 + 5 + 3 + 6 + 6 + 4 + 5);
+        assert_eq!(conversation.request_messages().unwrap().len(), 7);
+
+        // The turns take 16, 28 and 9 tokens, the system prompt 9
+        let conversation = conversation.clone().with_reserved_output(1000 - 46);
+        assert_eq!(
+            contents(&conversation.request_messages().unwrap()),
+            vec!["rules", "second", "", "text", "third"]
+        );
+        let conversation = conversation.with_reserved_output(1000 - 45);
+        assert_eq!(
+            contents(&conversation.request_messages().unwrap()),
+            vec!["rules", "third"]
+        );
+        let conversation = conversation.with_reserved_output(1000 - 17);
+        assert!(conversation.request_messages().is_err());
+    }
+
+    #[test]
+    fn test_keep_last_turns_and_fail() {
+        let conversation = conversation(1000).with_policy(TruncationPolicy::KeepLastTurns(2));
+        assert_eq!(
+            contents(&conversation.request_messages().unwrap()),
+            vec!["rules", "second", "", "text", "third"]
+        );
+        let conversation = conversation
+            .with_policy(TruncationPolicy::Fail)
+            .with_reserved_output(1000 - 60);
+        assert!(conversation.request_messages().is_err());
+    }
+
+    #[test]
+    fn test_reasoning() {
+        let mut conversation = conversation(1000);
+        let mut answer = ChatMessage::assistant("four");
+        answer.reasoning = Some("2 + 2".to_string());
+        conversation.push(answer);
+        let tokens = conversation.tokens();
+
+        let sent = conversation.request_messages().unwrap();
+        assert!(sent.iter().all(|m| m.reasoning.is_none()));
+        assert!(conversation.messages()[7].reasoning.is_some());
+
+        let conversation = conversation.with_reasoning(true);
+        assert_eq!(conversation.tokens(), tokens + 5);
+        let sent = conversation.request_messages().unwrap();
+        assert_eq!(sent[7].reasoning.as_deref(), Some("2 + 2"));
+    }
+
+    #[test]
+    fn test_budget_counts_tools_and_schema() {
+        let conversation = conversation(1000).with_reserved_prompt(100);
+        assert_eq!(conversation.budget(), 900);
+        let tool = ToolDefinition {
+            name: "read".to_string(),
+            description: None,
+            parameters: serde_json::json!({}),
+        };
+        let conversation = conversation
+            .with_tools(&[tool])
+            .with_response_schema(&serde_json::json!({"type": "object"}));
+        let tool_json = r#"{"name":"read","description":null,"parameters":{}}"#;
+        let schema_json = r#"{"type":"object"}"#;
+        assert_eq!(
+            conversation.budget(),
+            900 - tool_json.len() - schema_json.len()
+        );
+    }
+
+    #[test]
+    fn test_for_model() {
+        let provider = Provider::provider(ProviderType::Anthropic);
+        let model = provider
+            .with_model("claude-3-5-haiku-latest")
+            .unwrap()
+            .with_max_tokens(2000);
+        let conversation = Conversation::for_model(&model);
+        assert_eq!(conversation.budget(), 200_000 - 2000);
+
+        let conversation = Conversation::for_model(&model.with_ctx(8192));
+        assert_eq!(conversation.budget(), 8192 - 2000);
+        let unknown = provider.with_model("my-finetune").unwrap();
+        assert_eq!(
+            Conversation::for_model(&unknown).budget(),
+            DEFAULT_CONTEXT_WINDOW - DEFAULT_OUTPUT_RESERVE
+        );
+    }
+}
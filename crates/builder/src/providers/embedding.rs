@@ -0,0 +1,133 @@
+use crate::providers::completion::Usage;
+use crate::providers::config::ProviderError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+// Texts sent in one request unless the config sets `embedding_batch_size`
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+// Vector for one input text
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Embedding(pub Vec<f32>);
+
+impl Embedding {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // 1.0 for vectors pointing the same way, 0.0 for unrelated ones
+    pub fn cosine_similarity(&self, other: &Embedding) -> f32 {
+        let dot: f32 = self.0.iter().zip(&other.0).map(|(a, b)| a * b).sum();
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norms = norm(&self.0) * norm(&other.0);
+        if norms == 0.0 { 0.0 } else { dot / norms }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct EmbeddingResult {
+    // Model that actually served the request
+    pub model: String,
+    // One vector per input, in input order
+    pub embeddings: Vec<Embedding>,
+    pub usage: Option<Usage>,
+}
+
+// Backends that turn texts into vectors. Built with
+// `ModelBuilder::build_embedder`, usually for the provider's embedding model.
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync + std::fmt::Debug {
+    fn model(&self) -> &str;
+
+    // Embed every text, splitting them into batches as needed
+    async fn embed(&self, texts: Vec<String>) -> Result<EmbeddingResult, ProviderError>;
+}
+
+// Run `embed_batch` over chunks of `texts` and join the results in order
+pub async fn embed_in_batches<F, Fut>(
+    texts: Vec<String>,
+    batch_size: usize,
+    embed_batch: F,
+) -> Result<EmbeddingResult, ProviderError>
+where
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<EmbeddingResult, ProviderError>>,
+{
+    let mut result = EmbeddingResult::default();
+    for batch in texts.chunks(batch_size.max(1)) {
+        let batch_result = embed_batch(batch.to_vec()).await?;
+        if batch_result.embeddings.len() != batch.len() {
+            return Err(ProviderError::ResponseParsing(format!(
+                "Expected {} embeddings, got {}",
+                batch.len(),
+                batch_result.embeddings.len()
+            )));
+        }
+        if result.model.is_empty() {
+            result.model = batch_result.model;
+        }
+        result.embeddings.extend(batch_result.embeddings);
+        result.usage = match (result.usage, batch_result.usage) {
+            (Some(total), Some(usage)) => Some(Usage {
+                prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+                completion_tokens: total.completion_tokens + usage.completion_tokens,
+                total_tokens: total.total_tokens + usage.total_tokens,
+            }),
+            (total, usage) => total.or(usage),
+        };
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_embed_in_batches() {
+        let batches = Mutex::new(Vec::new());
+        let texts: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        let result = embed_in_batches(texts, 2, |batch| {
+            batches.lock().unwrap().push(batch.len());
+            async move {
+                Ok(EmbeddingResult {
+                    model: "nomic-embed-text".to_string(),
+                    embeddings: batch
+                        .iter()
+                        .map(|t| Embedding(vec![t.parse().unwrap()]))
+                        .collect(),
+                    usage: Some(Usage {
+                        prompt_tokens: 1,
+                        completion_tokens: 0,
+                        total_tokens: 1,
+                    }),
+                })
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(*batches.lock().unwrap(), vec![2, 2, 1]);
+        assert_eq!(
+            result.embeddings,
+            (0..5)
+                .map(|i| Embedding(vec![i as f32]))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(result.usage.unwrap().total_tokens, 3);
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+        let a = Embedding(vec![1.0, 0.0]);
+        assert_eq!(a.cosine_similarity(&Embedding(vec![2.0, 0.0])), 1.0);
+        assert_eq!(a.cosine_similarity(&Embedding(vec![0.0, 3.0])), 0.0);
+        assert_eq!(a.cosine_similarity(&Embedding(vec![0.0, 0.0])), 0.0);
+    }
+}
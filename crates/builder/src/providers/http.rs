@@ -0,0 +1,150 @@
+use crate::providers::config::ProviderError;
+use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// HTTP client settings of a provider, set in providers.toml:
+//
+// [providers.http]
+// connect_timeout_secs = 5
+// read_timeout_secs = 60
+// proxy = "http://127.0.0.1:3128"
+// headers = { "HTTP-Referer" = "https://example.com", "X-Title" = "Code Bunnies" }
+// extra_body = { provider = { order = ["groq"] } }
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    // Whole request, including reading the answer. Defaults to the
+    // provider's own limit (2 minutes, 5 for Ollama).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+    // Longest pause between two reads, catches stalled streams
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_timeout_secs: Option<u64>,
+    // Proxy for every request, e.g. `http://host:port` or `socks5://host:port`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // Sent with every request
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    // Merged into the top level of every chat request body
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
+
+impl HttpConfig {
+    pub fn with_timeout(mut self, timeout_secs: u64) -> Self {
+        self.timeout_secs = Some(timeout_secs);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout_secs: u64) -> Self {
+        self.connect_timeout_secs = Some(timeout_secs);
+        self
+    }
+
+    pub fn with_read_timeout(mut self, timeout_secs: u64) -> Self {
+        self.read_timeout_secs = Some(timeout_secs);
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_extra_body(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.extra_body.insert(key.into(), value);
+        self
+    }
+
+    // Build the reqwest client, `default_timeout` applies unless
+    // `timeout_secs` is set
+    pub fn client(&self, default_timeout: Duration) -> Result<Client, ProviderError> {
+        let error = |what: &str, e: &dyn std::fmt::Display| {
+            ProviderError::Configuration(format!("{}: {}", what, e))
+        };
+        let mut builder = Client::builder().timeout(
+            self.timeout_secs
+                .map(Duration::from_secs)
+                .unwrap_or(default_timeout),
+        );
+        if let Some(secs) = self.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.read_timeout_secs {
+            builder = builder.read_timeout(Duration::from_secs(secs));
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| error(&format!("Invalid proxy {}", proxy), &e))?;
+            builder = builder.proxy(proxy);
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| error(&format!("Invalid header name {}", name), &e))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| error(&format!("Invalid value for header {}", name), &e))?;
+            headers.insert(name, value);
+        }
+        builder
+            .default_headers(headers)
+            .build()
+            .map_err(|e| error("Failed to create HTTP client", &e))
+    }
+
+    // Add `extra_body` to a request body, replacing fields with the same name
+    pub fn extend_body(&self, body: &mut serde_json::Value) {
+        if let Some(body) = body.as_object_mut() {
+            body.extend(self.extra_body.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_toml() {
+        let http: HttpConfig = toml::from_str(
+            r#"
+            connect_timeout_secs = 5
+            proxy = "http://127.0.0.1:3128"
+            headers = { "HTTP-Referer" = "https://example.com" }
+            extra_body = { provider = { order = ["groq"] } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(http.connect_timeout_secs, Some(5));
+        assert!(http.client(Duration::from_secs(120)).is_ok());
+
+        let mut body = json!({"model": "llama", "provider": "old"});
+        http.extend_body(&mut body);
+        assert_eq!(
+            body,
+            json!({"model": "llama", "provider": {"order": ["groq"]}})
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let timeout = Duration::from_secs(1);
+        let bad_header = HttpConfig::default().with_header("Bad Header", "x");
+        assert!(bad_header.client(timeout).is_err());
+        let bad_proxy = HttpConfig::default().with_proxy("::not a url");
+        assert!(bad_proxy.client(timeout).is_err());
+    }
+}
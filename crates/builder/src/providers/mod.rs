@@ -1,5 +1,25 @@
+pub mod anthropic;
+pub mod api_key;
+pub mod cache;
+pub mod cassette;
+pub mod catalog;
+pub mod chat;
+pub mod completion;
 pub mod config;
+pub mod content;
+pub mod conversation;
+pub mod embedding;
+pub mod http;
 pub mod models;
+pub mod ollama;
 pub mod openai;
 pub mod provider;
 pub mod providers;
+pub mod reasoning;
+pub mod retry;
+pub mod router;
+pub mod schema_prompt;
+pub mod stream;
+pub mod structured;
+pub mod tokenizer;
+pub mod tool_call;
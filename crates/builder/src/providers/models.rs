@@ -1,18 +1,62 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::string::ToString;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+use super::catalog::{KnownModel, ModelCapabilities};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone)]
 pub struct Models {
     pub name: String,
     pub model: String,
     pub params: Option<ModelParams>,
+    // Set by Providers::sync_models when the server no longer lists the model
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub missing: bool,
+    // Model used by `Provider::with_embedding_model`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub embedding: bool,
+    // How response schemas reach the model, set to `prompt` for servers
+    // that reject `response_format: json_schema`
+    #[serde(default, skip_serializing_if = "StructuredOutput::is_native")]
+    pub structured_output: StructuredOutput,
+    // Overrides the catalog, needed for request features of custom models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<ModelCapabilities>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuredOutput {
+    // Sent as `response_format` / `format` / forced tool by the provider
+    #[default]
+    Native,
+    // Rendered into the system prompt, answers are extracted and repaired
+    Prompt,
 }
+
+impl StructuredOutput {
+    pub fn is_native(&self) -> bool {
+        *self == StructuredOutput::Native
+    }
+}
+impl Models {
+    // Capabilities from the config, or from the catalog for known models
+    pub fn known_capabilities(&self) -> Option<ModelCapabilities> {
+        self.capabilities
+            .or_else(|| KnownModel::lookup(&self.model).map(|known| known.capabilities()))
+    }
+}
+
 impl From<&str> for Models {
     fn from(model: &str) -> Self {
         Self {
             name: model.to_string(),
             model: model.to_string(),
             params: None,
+            missing: false,
+            embedding: false,
+            structured_output: StructuredOutput::Native,
+            capabilities: None,
         }
     }
 }
@@ -22,11 +66,15 @@ impl From<String> for Models {
             name: model.clone(),
             model,
             params: None,
+            missing: false,
+            embedding: false,
+            structured_output: StructuredOutput::Native,
+            capabilities: None,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone)]
 pub struct ModelParams {
     pub ctx: Option<i32>,
     pub temperature: Option<f32>,
@@ -37,5 +85,11 @@ pub struct ModelParams {
     pub presence_penalty: Option<f32>,
     pub frequency_penalty: Option<f32>,
     pub n: Option<i32>,
+    pub seed: Option<i64>,
+    pub repeat_penalty: Option<f32>,
+    pub mirostat: Option<u8>,
+    pub mirostat_tau: Option<f32>,
+    pub mirostat_eta: Option<f32>,
+    pub keep_alive: Option<String>,
     pub stream: Option<bool>,
 }
@@ -0,0 +1,919 @@
+use super::provider::ApiType;
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::{CompletionChoice, CompletionResult, Usage};
+use crate::providers::config::{ProviderConfig, ProviderError};
+use crate::providers::content::{ContentPart, MessageContent};
+use crate::providers::embedding::{
+    DEFAULT_BATCH_SIZE, Embedding, EmbeddingProvider, EmbeddingResult, embed_in_batches,
+};
+use crate::providers::openai::ChatMessage;
+use crate::providers::reasoning::split_think_stream;
+use crate::providers::stream::{ChatDelta, ChatStream, ndjson_lines};
+use crate::providers::tool_call::{ToolCall, ToolDefinition};
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Ollama provider implementation for the native /api/chat and /api/generate
+// endpoints. Unlike the OpenAI compatible shim it passes `options` such as
+// num_ctx or mirostat through to the model.
+#[derive(Debug, Clone)]
+pub struct OllamaProvider {
+    client: Client,
+    config: Arc<ProviderConfig>,
+    api_base_url: String,
+}
+
+// Request structures
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct OllamaMessage {
+    role: String,
+    #[serde(default)]
+    content: String,
+    // Base64 encoded images, without the data URL prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    images: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OllamaToolCall>>,
+    // Reasoning of thinking models, sent when the request has `think: true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thinking: Option<String>,
+}
+
+// Ollama sends tool arguments as a JSON object instead of a string, and
+// older versions don't send an id at all
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct OllamaToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    function: OllamaFunction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct OllamaFunction {
+    name: String,
+    arguments: serde_json::Value,
+}
+
+// Response structures, shared by /api/chat and /api/generate and by every
+// line of their streaming responses
+#[derive(Debug, Deserialize)]
+struct OllamaResponse {
+    #[serde(default)]
+    model: String,
+    // Set by /api/chat
+    message: Option<OllamaMessage>,
+    // Set by /api/generate
+    response: Option<String>,
+    thinking: Option<String>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+    // Errors in the middle of a stream come as a line with only this field
+    error: Option<String>,
+}
+
+// Response of /api/embed
+#[derive(Debug, Deserialize)]
+struct OllamaEmbeddings {
+    #[serde(default)]
+    model: String,
+    embeddings: Vec<Vec<f32>>,
+    prompt_eval_count: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTag {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaTag>,
+}
+
+impl From<ChatMessage> for OllamaMessage {
+    fn from(message: ChatMessage) -> Self {
+        let tool_calls = message.tool_calls.map(|calls| {
+            calls
+                .into_iter()
+                .map(|call| OllamaToolCall {
+                    id: Some(call.id),
+                    function: OllamaFunction {
+                        arguments: serde_json::from_str(&call.function.arguments)
+                            .unwrap_or(json!(call.function.arguments)),
+                        name: call.function.name,
+                    },
+                })
+                .collect()
+        });
+        // Linked images were downloaded by OllamaProvider::inline_images
+        let images: Vec<String> = message
+            .content
+            .images()
+            .filter_map(|image| image.as_base64().map(|(_, data)| data.to_string()))
+            .collect();
+        OllamaMessage {
+            role: message.role,
+            content: message.content.into_text(),
+            images: (!images.is_empty()).then_some(images),
+            tool_calls,
+            thinking: message.reasoning,
+        }
+    }
+}
+
+impl From<OllamaMessage> for ChatMessage {
+    fn from(message: OllamaMessage) -> Self {
+        let mut chat_message = ChatMessage::assistant(message.content);
+        chat_message.role = message.role;
+        chat_message.tool_calls = message.tool_calls.map(|calls| {
+            calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, call)| {
+                    ToolCall::new(
+                        call.id.unwrap_or_else(|| format!("call_{}", i)),
+                        call.function.name,
+                        call.function.arguments.to_string(),
+                    )
+                })
+                .collect()
+        });
+        chat_message.reasoning = message.thinking.filter(|t| !t.is_empty());
+        chat_message
+    }
+}
+
+impl OllamaResponse {
+    // `reasoning_model` as in reasoning::split_think
+    fn into_completion(self, reasoning_model: bool) -> Result<CompletionResult, ProviderError> {
+        if let Some(error) = self.error {
+            return Err(ProviderError::ApiCall(error));
+        }
+        let mut message = match (self.message, self.response) {
+            (Some(message), _) => ChatMessage::from(message),
+            (None, Some(response)) => ChatMessage {
+                reasoning: self.thinking.filter(|t| !t.is_empty()),
+                ..ChatMessage::assistant(response)
+            },
+            (None, None) => {
+                return Err(ProviderError::ResponseParsing(
+                    "No message returned from Ollama".to_string(),
+                ));
+            }
+        };
+        // Models without `think` support in Ollama answer with the tags
+        message.split_reasoning(reasoning_model);
+        let usage = match (self.prompt_eval_count, self.eval_count) {
+            (None, None) => None,
+            (prompt, completion) => {
+                let prompt_tokens = prompt.unwrap_or_default();
+                let completion_tokens = completion.unwrap_or_default();
+                Some(Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
+                })
+            }
+        };
+        Ok(CompletionResult {
+            id: String::new(),
+            model: self.model,
+            created: None,
+            choices: vec![CompletionChoice {
+                index: 0,
+                message,
+                finish_reason: self.done_reason,
+            }],
+            usage,
+        })
+    }
+}
+
+// Convert one line of a streaming response into a delta
+fn parse_line(line: &str) -> Result<ChatDelta, ProviderError> {
+    let chunk: OllamaResponse = serde_json::from_str(line).map_err(|e| {
+        ProviderError::ResponseParsing(format!("Failed to parse Ollama stream line: {}", e))
+    })?;
+    if let Some(error) = chunk.error {
+        return Err(ProviderError::ApiCall(error));
+    }
+    let (content, reasoning) = match chunk.message {
+        Some(message) => (message.content, message.thinking),
+        None => (chunk.response.unwrap_or_default(), chunk.thinking),
+    };
+    Ok(ChatDelta {
+        content,
+        reasoning: reasoning.unwrap_or_default(),
+        finish_reason: chunk.done.then(|| chunk.done_reason.unwrap_or_default()),
+    })
+}
+
+// List the models pulled into the Ollama server at `root_url`
+pub async fn list_tags(
+    client: &Client,
+    root_url: &str,
+    config: &ProviderConfig,
+) -> Result<Vec<String>, ProviderError> {
+    let url = format!("{}/api/tags", root_url);
+    let response = config.send(|| client.get(&url)).await?;
+    let tags: OllamaTags = config
+        .unless_cancelled(response.json())
+        .await?
+        .map_err(|e| {
+            ProviderError::ResponseParsing(format!("Failed to parse Ollama model list: {}", e))
+        })?;
+    Ok(tags.models.into_iter().map(|m| m.name).collect())
+}
+
+// Ollama serves its native API from the root, configs written for the
+// OpenAI shim point at `/v1`
+pub fn root_url(base_url: &str) -> &str {
+    base_url.trim_end_matches('/').trim_end_matches("/v1")
+}
+
+impl OllamaProvider {
+    pub fn new(config: Arc<ProviderConfig>) -> Result<Self, ProviderError> {
+        if config.api_type != ApiType::Ollama {
+            return Err(ProviderError::Configuration(
+                "Incorrect ApiType for OllamaProvider".to_string(),
+            ));
+        }
+
+        // Local models can take a while to load, so be generous
+        let client = config.http.client(Duration::from_secs(300))?;
+
+        let api_base_url = root_url(
+            config
+                .api_base_url
+                .as_deref()
+                .unwrap_or("http://127.0.0.1:11434"),
+        )
+        .to_string();
+
+        Ok(OllamaProvider {
+            client,
+            config,
+            api_base_url,
+        })
+    }
+
+    // Sampling settings in the shape of Ollama's `options` object
+    fn options(
+        &self,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+    ) -> serde_json::Value {
+        let config = &self.config;
+        let mut options = json!({});
+        if let Some(temp) = temperature_override.or(config.temperature) {
+            options["temperature"] = json!(temp);
+        }
+        if let Some(tokens) = max_tokens_override.or_else(|| config.max_tokens.map(|t| t as u32)) {
+            options["num_predict"] = json!(tokens);
+        }
+        if let Some(ctx) = config.ctx_size {
+            options["num_ctx"] = json!(ctx);
+        }
+        if let Some(top_p) = config.top_p {
+            options["top_p"] = json!(top_p);
+        }
+        if let Some(top_k) = config.top_k {
+            options["top_k"] = json!(top_k);
+        }
+        if let Some(stop) = &config.stop {
+            options["stop"] = json!(stop);
+        }
+        if let Some(penalty) = config.presence_penalty {
+            options["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = config.frequency_penalty {
+            options["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = config.repeat_penalty {
+            options["repeat_penalty"] = json!(penalty);
+        }
+        if let Some(seed) = config.seed {
+            options["seed"] = json!(seed);
+        }
+        if let Some(mirostat) = config.mirostat {
+            options["mirostat"] = json!(mirostat);
+        }
+        if let Some(tau) = config.mirostat_tau {
+            options["mirostat_tau"] = json!(tau);
+        }
+        if let Some(eta) = config.mirostat_eta {
+            options["mirostat_eta"] = json!(eta);
+        }
+        options
+    }
+
+    // Fields shared by /api/chat and /api/generate
+    fn base_body(
+        &self,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+        stream: bool,
+    ) -> serde_json::Value {
+        let mut request_body = json!({
+            "model": model_override.unwrap_or_else(|| self.config.model.clone()),
+            "stream": stream,
+            "options": self.options(temperature_override, max_tokens_override),
+        });
+        if let Some(keep_alive) = &self.config.keep_alive {
+            request_body["keep_alive"] = json!(keep_alive);
+        }
+        // `format` takes a full JSON schema
+        if let Some(schema) = response_schema {
+            request_body["format"] = schema;
+        }
+        self.config.http.extend_body(&mut request_body);
+        request_body
+    }
+
+    async fn chat_body(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+        stream: bool,
+    ) -> Result<serde_json::Value, ProviderError> {
+        let messages = self.inline_images(messages).await?;
+        let mut request_body = self.base_body(
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+            stream,
+        );
+        request_body["messages"] = json!(
+            self.config
+                .outgoing_messages(messages)
+                .into_iter()
+                .map(OllamaMessage::from)
+                .collect::<Vec<_>>()
+        );
+        Ok(request_body)
+    }
+
+    // Ollama only takes base64 images, linked ones are downloaded here
+    async fn inline_images(
+        &self,
+        mut messages: Vec<ChatMessage>,
+    ) -> Result<Vec<ChatMessage>, ProviderError> {
+        for message in &mut messages {
+            let MessageContent::Parts(parts) = &mut message.content else {
+                continue;
+            };
+            for part in parts {
+                if let ContentPart::ImageUrl { image_url } = part
+                    && image_url.as_base64().is_none()
+                {
+                    *part = self.download_image(&image_url.url).await?;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn download_image(&self, url: &str) -> Result<ContentPart, ProviderError> {
+        let error = |e: reqwest::Error| {
+            ProviderError::RequestPreparation(format!("Cannot download image {}: {}", url, e))
+        };
+        let response = self
+            .config
+            .unless_cancelled(self.client.get(url).send())
+            .await?
+            .and_then(|response| response.error_for_status())
+            .map_err(error)?;
+        let media_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .unwrap_or("image/png")
+            .trim()
+            .to_string();
+        let bytes = self
+            .config
+            .unless_cancelled(response.bytes())
+            .await?
+            .map_err(error)?;
+        Ok(ContentPart::image_bytes(&media_type, &bytes))
+    }
+
+    async fn post(
+        &self,
+        path: &str,
+        request_body: &serde_json::Value,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let url = format!("{}{}", self.api_base_url, path);
+        self.config
+            .send(|| self.client.post(&url).json(request_body))
+            .await
+    }
+
+    // Send a non-streaming request and parse the response
+    async fn execute(
+        &self,
+        path: &str,
+        request_body: &serde_json::Value,
+    ) -> Result<CompletionResult, ProviderError> {
+        let response = self.post(path, request_body).await?;
+        let response: OllamaResponse = self
+            .config
+            .unless_cancelled(response.json())
+            .await?
+            .map_err(|e| {
+                ProviderError::ResponseParsing(format!("Failed to parse Ollama response: {}", e))
+            })?;
+        response.into_completion(self.config.reasoning)
+    }
+
+    async fn stream(
+        &self,
+        path: &str,
+        request_body: &serde_json::Value,
+    ) -> Result<ChatStream, ProviderError> {
+        let response = self.post(path, request_body).await?;
+        let deltas = ndjson_lines(response).map(|line| line.and_then(|line| parse_line(&line)));
+        let deltas = split_think_stream(Box::pin(deltas));
+        Ok(self.config.cancellable(deltas))
+    }
+
+    // Plain completion of `prompt` through /api/generate, without chat template
+    // turns. `system` replaces the system prompt from the Modelfile.
+    pub async fn generate(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let mut request_body = self.base_body(None, None, None, response_schema, false);
+        request_body["prompt"] = json!(prompt);
+        if let Some(system) = system {
+            request_body["system"] = json!(system);
+        }
+        self.execute("/api/generate", &request_body).await
+    }
+
+    // Same as generate, but returns the answer as a stream of deltas
+    pub async fn generate_stream(
+        &self,
+        prompt: &str,
+        system: Option<&str>,
+    ) -> Result<ChatStream, ProviderError> {
+        let mut request_body = self.base_body(None, None, None, None, true);
+        request_body["prompt"] = json!(prompt);
+        if let Some(system) = system {
+            request_body["system"] = json!(system);
+        }
+        self.stream("/api/generate", &request_body).await
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OllamaProvider {
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let request_body = self
+            .chat_body(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                response_schema,
+                false,
+            )
+            .await?;
+        self.execute("/api/chat", &request_body).await
+    }
+
+    // Deltas are parsed from the newline delimited JSON response
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        let request_body = self
+            .chat_body(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                response_schema,
+                true,
+            )
+            .await?;
+        self.stream("/api/chat", &request_body).await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        let mut request_body = self
+            .chat_body(messages, None, None, None, None, false)
+            .await?;
+        request_body["tools"] = json!(tools.iter().map(|t| t.to_openai()).collect::<Vec<_>>());
+        self.execute("/api/chat", &request_body)
+            .await?
+            .into_message()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        list_tags(&self.client, &self.api_base_url, &self.config).await
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OllamaProvider {
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn embed(&self, texts: Vec<String>) -> Result<EmbeddingResult, ProviderError> {
+        let batch_size = self
+            .config
+            .embedding_batch_size
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+        embed_in_batches(texts, batch_size, |batch| async move {
+            let mut request_body = json!({"model": self.config.model, "input": batch});
+            if let Some(keep_alive) = &self.config.keep_alive {
+                request_body["keep_alive"] = json!(keep_alive);
+            }
+            let response = self.post("/api/embed", &request_body).await?;
+            let response: OllamaEmbeddings = self
+                .config
+                .unless_cancelled(response.json())
+                .await?
+                .map_err(|e| {
+                ProviderError::ResponseParsing(format!("Failed to parse Ollama embeddings: {}", e))
+            })?;
+            Ok(EmbeddingResult {
+                model: response.model,
+                embeddings: response.embeddings.into_iter().map(Embedding).collect(),
+                usage: response.prompt_eval_count.map(|tokens| Usage {
+                    prompt_tokens: tokens,
+                    completion_tokens: 0,
+                    total_tokens: tokens,
+                }),
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::stream::collect_stream;
+    use mockito::Matcher;
+
+    fn provider(config: ProviderConfig, base_url: String) -> OllamaProvider {
+        OllamaProvider::new(Arc::new(config.with_api_base_url(base_url))).unwrap()
+    }
+
+    fn config() -> ProviderConfig {
+        ProviderConfig::new(ApiType::Ollama, "granite3.2".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_chat_maps_options() {
+        let mut server = mockito::Server::new_async().await;
+        let schema = json!({"type": "object", "properties": {"sum": {"type": "integer"}}});
+        let mock = server
+            .mock("POST", "/api/chat")
+            .match_body(Matcher::PartialJson(json!({
+                "model": "granite3.2",
+                "stream": false,
+                "keep_alive": "10m",
+                "format": schema,
+                "messages": [{"role": "user", "content": "1+2"}],
+                "options": {
+                    "num_ctx": 16384,
+                    "num_predict": 64,
+                    "top_k": 20,
+                    "repeat_penalty": 1.5,
+                    "mirostat": 2
+                }
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "model": "granite3.2",
+                    "created_at": "2025-03-20T10:00:00Z",
+                    "message": {"role": "assistant", "content": "{\"sum\":3}"},
+                    "done": true,
+                    "done_reason": "stop",
+                    "prompt_eval_count": 30,
+                    "eval_count": 6
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let config = config()
+            .with_ctx_size(16384)
+            .with_max_tokens(64)
+            .with_top_k(20)
+            .with_repeat_penalty(1.5)
+            .with_mirostat(2)
+            .with_keep_alive("10m".to_string());
+        // Base urls written for the OpenAI shim still work
+        let result = provider(config, format!("{}/v1", server.url()))
+            .complete(
+                vec![ChatMessage::user("1+2")],
+                None,
+                None,
+                None,
+                Some(schema),
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(result.content(), Some("{\"sum\":3}"));
+        assert_eq!(result.finish_reason(), Some("stop"));
+        assert_eq!(result.usage.unwrap().total_tokens, 36);
+    }
+
+    #[tokio::test]
+    async fn test_linked_images_are_downloaded() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cat.png")
+            .with_status(200)
+            .with_header("content-type", "image/png")
+            .with_body("png")
+            .create_async()
+            .await;
+        let chat = server
+            .mock("POST", "/api/chat")
+            .match_body(Matcher::PartialJson(json!({
+                "messages": [{"role": "user", "content": "What is this?", "images": ["cG5n"]}]
+            })))
+            .with_status(200)
+            .with_body(
+                json!({"message": {"role": "assistant", "content": "A cat"}, "done": true})
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let provider = provider(config(), server.url());
+        let ask = |url: String| {
+            vec![ChatMessage::user(vec![
+                ContentPart::text("What is this?"),
+                ContentPart::image_url(url),
+            ])]
+        };
+        let answer = provider
+            .chat_completion(
+                ask(format!("{}/cat.png", server.url())),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        chat.assert_async().await;
+        assert_eq!(answer, "A cat");
+
+        let error = provider
+            .chat_completion(
+                ask(format!("{}/dog.png", server.url())),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Cannot download image"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream() {
+        let mut server = mockito::Server::new_async().await;
+        let body = [
+            r#"{"model":"granite3.2","message":{"role":"assistant","content":"","thinking":"Say hi"},"done":false}"#,
+            r#"{"model":"granite3.2","message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            r#"{"model":"granite3.2","message":{"role":"assistant","content":"lo"},"done":false}"#,
+            r#"{"model":"granite3.2","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","eval_count":2}"#,
+        ]
+        .join("\n");
+        let mock = server
+            .mock("POST", "/api/chat")
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "application/x-ndjson")
+            .with_body(body)
+            .create_async()
+            .await;
+
+        let stream = provider(config(), server.url())
+            .chat_completion_stream(vec![ChatMessage::user("hi")], None, None, None, None)
+            .await
+            .unwrap();
+        let collected = collect_stream(stream).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(collected.message.content, "Hello");
+        assert_eq!(collected.message.reasoning.as_deref(), Some("Say hi"));
+        assert_eq!(collected.finish_reason.as_deref(), Some("length"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_error_line() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/generate")
+            .with_status(200)
+            .with_body(
+                "{\"response\":\"a\",\"done\":false}\n{\"error\":\"model runner has unexpectedly stopped\"}\n",
+            )
+            .create_async()
+            .await;
+
+        let stream = provider(config(), server.url())
+            .generate_stream("write a poem", None)
+            .await
+            .unwrap();
+        let error = collect_stream(stream).await.unwrap_err();
+        assert!(error.to_string().contains("unexpectedly stopped"));
+    }
+
+    #[tokio::test]
+    async fn test_generate() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/generate")
+            .match_body(Matcher::PartialJson(json!({
+                "prompt": "fn add(a: i32, b: i32) -> i32 {",
+                "system": "complete the code",
+                "stream": false
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "model": "granite3.2",
+                    "response": " a + b }",
+                    "done": true,
+                    "done_reason": "stop"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let result = provider(config(), server.url())
+            .generate(
+                "fn add(a: i32, b: i32) -> i32 {",
+                Some("complete the code"),
+                None,
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(result.into_content().unwrap(), " a + b }");
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_tools() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/chat")
+            .match_body(Matcher::PartialJson(json!({
+                "tools": [{"type": "function", "function": {"name": "read_file"}}],
+                "messages": [
+                    {"role": "user", "content": "open main"},
+                    {"role": "assistant", "tool_calls": [{"function": {"name": "read_file", "arguments": {"file_path": "a.rs"}}}]},
+                    {"role": "tool", "content": "missing"}
+                ]
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "model": "granite3.2",
+                    "message": {
+                        "role": "assistant",
+                        "content": "",
+                        "tool_calls": [{"function": {"name": "read_file", "arguments": {"file_path": "src/main.rs"}}}]
+                    },
+                    "done": true,
+                    "done_reason": "stop"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut call = ChatMessage::assistant("");
+        call.tool_calls = Some(vec![ToolCall::new(
+            "call_0",
+            "read_file",
+            r#"{"file_path":"a.rs"}"#.to_string(),
+        )]);
+        let tools = vec![ToolDefinition {
+            name: "read_file".to_string(),
+            description: None,
+            parameters: json!({"type": "object"}),
+        }];
+        let message = provider(config(), server.url())
+            .chat_with_tools(
+                vec![
+                    ChatMessage::user("open main"),
+                    call,
+                    ChatMessage::tool("call_0", "missing"),
+                ],
+                tools,
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].function.name, "read_file");
+        assert_eq!(
+            calls[0].function.arguments,
+            r#"{"file_path":"src/main.rs"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_models() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/api/tags")
+            .with_status(200)
+            .with_body(json!({"models": [{"name": "gemma3:latest"}]}).to_string())
+            .create_async()
+            .await;
+
+        let models = provider(config(), server.url())
+            .list_models()
+            .await
+            .unwrap();
+        assert_eq!(models, vec!["gemma3:latest"]);
+    }
+
+    #[tokio::test]
+    async fn test_embed() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/embed")
+            .match_body(Matcher::Json(
+                json!({"model": "nomic-embed-text", "input": ["a", "b"]}),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "model": "nomic-embed-text",
+                    "embeddings": [[0.1, 0.2], [0.3, 0.4]],
+                    "prompt_eval_count": 2
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let config = ProviderConfig::new(ApiType::Ollama, "nomic-embed-text".to_string());
+        let result = provider(config, server.url())
+            .embed(vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        assert_eq!(result.embeddings[1], Embedding(vec![0.3, 0.4]));
+        assert_eq!(result.usage.unwrap().prompt_tokens, 2);
+    }
+}
@@ -1,9 +1,23 @@
-use super::provider::ApiType;
+use super::provider::{ApiType, ProviderType};
+use crate::providers::api_key::CachedKey;
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::{CompletionResult, Usage};
 use crate::providers::config::{ProviderConfig, ProviderError};
+use crate::providers::content::MessageContent;
+use crate::providers::embedding::{
+    DEFAULT_BATCH_SIZE, Embedding, EmbeddingProvider, EmbeddingResult, embed_in_batches,
+};
+use crate::providers::ollama;
+use crate::providers::reasoning::{split_think, split_think_stream};
+use crate::providers::stream::{ChatDelta, ChatStream, sse_events};
+use crate::providers::tool_call::{ToolCall, ToolDefinition};
+use async_trait::async_trait;
+use futures::StreamExt;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
 use std::sync::Arc;
+use std::time::Duration;
 
 // OpenAI provider implementation using reqwest directly
 #[derive(Debug, Clone)]
@@ -11,53 +25,173 @@ pub struct OpenAiProvider {
     client: Client,
     config: Arc<ProviderConfig>,
     api_base_url: String,
+    api_key: CachedKey,
 }
 
 // Structures for serializing/deserializing OpenAI API messages
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
-    pub content: String,
+    // Assistant messages that only call tools come back with `content: null`
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // Set on `tool` messages, the id of the call this message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    // Thinking of a reasoning model, kept apart from the answer. DeepSeek,
+    // LM Studio, vLLM and llama.cpp send it as `reasoning_content`.
+    #[serde(
+        default,
+        rename = "reasoning_content",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reasoning: Option<String>,
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MessageContent, D::Error> {
+    Ok(Option::<MessageContent>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 impl ChatMessage {
-    pub fn system(content: impl Into<String>) -> Self {
+    fn new(role: &str, content: MessageContent) -> Self {
         ChatMessage {
-            role: "system".to_string(),
-            content: content.into(),
+            role: role.to_string(),
+            content,
+            tool_calls: None,
+            tool_call_id: None,
+            reasoning: None,
         }
     }
 
-    pub fn user(content: impl Into<String>) -> Self {
+    pub fn system(content: impl Into<MessageContent>) -> Self {
+        Self::new("system", content.into())
+    }
+
+    // Takes plain text or a list of text and image parts:
+    // ChatMessage::user(vec![ContentPart::text("What is this?"), ContentPart::image_file(path)?])
+    pub fn user(content: impl Into<MessageContent>) -> Self {
+        Self::new("user", content.into())
+    }
+
+    pub fn assistant(content: impl Into<MessageContent>) -> Self {
+        Self::new("assistant", content.into())
+    }
+
+    // Result of a tool call, sent back to the model
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
         ChatMessage {
-            role: "user".to_string(),
-            content: content.into(),
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", MessageContent::Text(content.into()))
+        }
+    }
+
+    pub fn has_tool_calls(&self) -> bool {
+        self.tool_calls
+            .as_ref()
+            .is_some_and(|calls| !calls.is_empty())
+    }
+
+    // Move a leading `<think>` block of a text answer into `reasoning`, see
+    // reasoning::split_think
+    pub fn split_reasoning(&mut self, reasoning_model: bool) {
+        let Some((reasoning, content)) = self
+            .content
+            .as_text()
+            .and_then(|text| split_think(text, reasoning_model))
+        else {
+            return;
+        };
+        self.content = MessageContent::Text(content);
+        if !reasoning.is_empty() {
+            self.reasoning = Some(match self.reasoning.take() {
+                Some(earlier) => format!("{}\n\n{}", earlier, reasoning),
+                None => reasoning,
+            });
         }
     }
 
-    pub fn assistant(content: impl Into<String>) -> Self {
+    // The message as it goes back into a request, without the thinking
+    pub fn without_reasoning(self) -> Self {
         ChatMessage {
-            role: "assistant".to_string(),
-            content: content.into(),
+            reasoning: None,
+            ..self
         }
     }
 }
 
-// Response structures
+// Streaming response structures
+#[derive(Debug, Deserialize, Default)]
+struct ChunkDelta {
+    content: Option<String>,
+    reasoning_content: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
-struct ChatChoice {
-    message: ChatMessage,
-    finish_reason: Option<String>,
+struct ChunkChoice {
+    #[serde(default)]
     index: usize,
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    choices: Vec<ChunkChoice>,
 }
 
+// Response of the `/models` endpoint
 #[derive(Debug, Deserialize)]
-struct ChatCompletionResponse {
+struct ModelEntry {
     id: String,
-    object: String,
-    created: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+// Response of the `/embeddings` endpoint
+#[derive(Debug, Deserialize)]
+struct EmbeddingEntry {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingList {
+    data: Vec<EmbeddingEntry>,
+    #[serde(default)]
     model: String,
-    choices: Vec<ChatChoice>,
+    usage: Option<Usage>,
+}
+
+// Convert one `data:` payload of the stream into a delta, skipping
+// the `[DONE]` sentinel and chunks that carry neither text nor a finish reason.
+// With `n` above 1 the choices are interleaved, only the first one is kept.
+fn parse_chunk(data: &str) -> Option<Result<ChatDelta, ProviderError>> {
+    if data == "[DONE]" {
+        return None;
+    }
+    let chunk: ChatCompletionChunk = match serde_json::from_str(data) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            return Some(Err(ProviderError::ResponseParsing(format!(
+                "Failed to parse OpenAI stream chunk: {}",
+                e
+            ))));
+        }
+    };
+    let choice = chunk.choices.into_iter().find(|choice| choice.index == 0)?;
+    let delta = ChatDelta {
+        content: choice.delta.content.unwrap_or_default(),
+        reasoning: choice.delta.reasoning_content.unwrap_or_default(),
+        finish_reason: choice.finish_reason,
+    };
+    (!delta.is_empty()).then_some(Ok(delta))
 }
 
 impl OpenAiProvider {
@@ -68,19 +202,14 @@ impl OpenAiProvider {
             ));
         }
 
-        let api_key = config
-            .api_key
-            .as_ref()
-            .ok_or_else(|| ProviderError::Configuration("OpenAI API key is missing".to_string()))?;
-
-        // Create a reqwest client with appropriate timeout settings
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(120)) // 2 minutes timeout
-            .build()
-            .map_err(|e| {
-                ProviderError::Configuration(format!("Failed to create HTTP client: {}", e))
+        let api_key =
+            config.api_key.clone().map(CachedKey::new).ok_or_else(|| {
+                ProviderError::Configuration("OpenAI API key is missing".to_string())
             })?;
 
+        // Timeouts, proxy and headers come from the provider's `http` settings
+        let client = config.http.client(Duration::from_secs(120))?;
+
         // Default to the official OpenAI API endpoint, but allow override via config
         let api_base_url = config
             .api_base_url
@@ -91,24 +220,19 @@ impl OpenAiProvider {
             client,
             config,
             api_base_url,
+            api_key,
         })
     }
 
-    pub async fn chat_completion(
+    // Build the JSON body shared by the blocking and streaming requests
+    fn request_body(
         &self,
         messages: Vec<ChatMessage>,
         model_override: Option<String>,
         temperature_override: Option<f32>,
         max_tokens_override: Option<u32>,
-        response_schema: Option<serde_json::Value>, // Add schema as an optional parameter
-                                                    // Optional schema for response format
-                                                    // Add other parameters as needed
-    ) -> Result<String, ProviderError> {
-        let api_key =
-            self.config.api_key.as_ref().ok_or_else(|| {
-                ProviderError::Configuration("OpenAI API key is missing".to_string())
-            })?;
-
+        response_schema: Option<serde_json::Value>,
+    ) -> serde_json::Value {
         let model = model_override.unwrap_or_else(|| self.config.model.clone());
         let temperature = temperature_override.or(self.config.temperature);
         let max_tokens = max_tokens_override.or_else(|| self.config.max_tokens.map(|t| t as u32));
@@ -116,7 +240,7 @@ impl OpenAiProvider {
         // Construct the request body
         let mut request_body = json!({
             "model": model,
-            "messages": messages,
+            "messages": self.config.outgoing_messages(messages),
         });
 
         // Add optional parameters if provided
@@ -126,6 +250,32 @@ impl OpenAiProvider {
         if let Some(tokens) = max_tokens {
             request_body["max_tokens"] = json!(tokens);
         }
+        // Sampling parameters, validate() made sure the backend accepts them
+        let config = &self.config;
+        if let Some(top_p) = config.top_p {
+            request_body["top_p"] = json!(top_p);
+        }
+        if let Some(top_k) = config.top_k {
+            request_body["top_k"] = json!(top_k);
+        }
+        if let Some(stop) = &config.stop {
+            request_body["stop"] = json!(stop);
+        }
+        if let Some(penalty) = config.presence_penalty {
+            request_body["presence_penalty"] = json!(penalty);
+        }
+        if let Some(penalty) = config.frequency_penalty {
+            request_body["frequency_penalty"] = json!(penalty);
+        }
+        if let Some(n) = config.n {
+            request_body["n"] = json!(n);
+        }
+        if let Some(seed) = config.seed {
+            request_body["seed"] = json!(seed);
+        }
+        if let Some(penalty) = config.repeat_penalty {
+            request_body["repeat_penalty"] = json!(penalty);
+        }
         // Add the response_format if a schema is provided
         if let Some(schema) = response_schema {
             request_body["response_format"] = json!({
@@ -136,50 +286,589 @@ impl OpenAiProvider {
                 }
             });
         }
+        config.http.extend_body(&mut request_body);
 
-        // Add other parameters as needed
-        // request_body["top_p"] = ...
+        request_body
+    }
+
+    // Send a request body to the chat completions endpoint, retrying
+    // according to the config
+    async fn send(
+        &self,
+        request_body: &serde_json::Value,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let api_key = self.api_key.get().await?;
 
         // Construct the full API URL
         let url = format!("{}/chat/completions", self.api_base_url);
 
         // Send the request to OpenAI
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&request_body)
-            .send()
+        self.config
+            .send(|| {
+                self.client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .json(request_body)
+            })
             .await
-            .map_err(|e| ProviderError::ApiCall(format!("Request to OpenAI failed: {}", e)))?;
+    }
 
-        // Check for non-success status codes
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "<could not read error response>".to_string());
-            return Err(ProviderError::ApiCall(format!(
-                "OpenAI API returned non-success status code {}: {}",
-                status, error_text
-            )));
-        }
+    // Send a non-streaming request and parse the full response
+    async fn execute(
+        &self,
+        request_body: &serde_json::Value,
+    ) -> Result<CompletionResult, ProviderError> {
+        let response = self.send(request_body).await?;
 
         // Parse the response
-        let completion_response: ChatCompletionResponse = response.json().await.map_err(|e| {
-            ProviderError::ResponseParsing(format!("Failed to parse OpenAI response: {}", e))
-        })?;
-
-        // Extract the generated text from the first choice
-        completion_response
-            .choices
-            .into_iter()
-            .next()
-            .map(|choice| choice.message.content)
-            .ok_or_else(|| {
-                ProviderError::ResponseParsing("No completions returned from OpenAI".to_string())
+        let mut result: CompletionResult = self
+            .config
+            .unless_cancelled(response.json())
+            .await?
+            .map_err(|e| {
+                ProviderError::ResponseParsing(format!("Failed to parse OpenAI response: {}", e))
+            })?;
+        if result.choices.is_empty() {
+            return Err(ProviderError::ResponseParsing(
+                "No completions returned from OpenAI".to_string(),
+            ));
+        }
+        result.split_reasoning(self.config.reasoning);
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OpenAiProvider {
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>, // Add schema as an optional parameter
+                                                    // Optional schema for response format
+                                                    // Add other parameters as needed
+    ) -> Result<CompletionResult, ProviderError> {
+        let request_body = self.request_body(
+            messages,
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+        );
+        self.execute(&request_body).await
+    }
+
+    // Deltas are parsed from the `text/event-stream` response
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        let mut request_body = self.request_body(
+            messages,
+            model_override,
+            temperature_override,
+            max_tokens_override,
+            response_schema,
+        );
+        request_body["stream"] = json!(true);
+        let response = self.send(&request_body).await?;
+
+        let deltas = sse_events(response).filter_map(|event| async move {
+            match event {
+                Ok(event) => parse_chunk(&event.data),
+                Err(e) => Some(Err(e)),
+            }
+        });
+        let deltas = split_think_stream(Box::pin(deltas));
+        Ok(self.config.cancellable(deltas))
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        let mut request_body = self.request_body(messages, None, None, None, None);
+        request_body["tools"] = json!(tools.iter().map(|t| t.to_openai()).collect::<Vec<_>>());
+        request_body["tool_choice"] = json!("auto");
+        self.execute(&request_body).await?.into_message()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        // Ollama lists every pulled model under its native API, `/v1/models`
+        // only knows about the OpenAI compatible ones
+        if self.config.provider_type == ProviderType::Ollama {
+            let root = ollama::root_url(&self.api_base_url);
+            return ollama::list_tags(&self.client, root, &self.config).await;
+        }
+        let api_key = self.api_key.get().await?;
+        let url = format!("{}/models", self.api_base_url);
+        let response = self
+            .config
+            .send(|| {
+                self.client
+                    .get(&url)
+                    .header("Authorization", format!("Bearer {}", api_key))
             })
+            .await?;
+        let models: ModelList = self
+            .config
+            .unless_cancelled(response.json())
+            .await?
+            .map_err(|e| {
+                ProviderError::ResponseParsing(format!("Failed to parse OpenAI model list: {}", e))
+            })?;
+        Ok(models.data.into_iter().map(|m| m.id).collect())
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAiProvider {
+    fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    async fn embed(&self, texts: Vec<String>) -> Result<EmbeddingResult, ProviderError> {
+        let api_key = self.api_key.get().await?;
+        let url = format!("{}/embeddings", self.api_base_url);
+        let batch_size = self
+            .config
+            .embedding_batch_size
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+        embed_in_batches(texts, batch_size, |batch| {
+            let request_body = json!({"model": self.config.model, "input": batch});
+            let (api_key, url) = (&api_key, &url);
+            async move {
+                let response = self
+                    .config
+                    .send(|| {
+                        self.client
+                            .post(url)
+                            .header("Authorization", format!("Bearer {}", api_key))
+                            .json(&request_body)
+                    })
+                    .await?;
+                let mut list: EmbeddingList = self
+                    .config
+                    .unless_cancelled(response.json())
+                    .await?
+                    .map_err(|e| {
+                        ProviderError::ResponseParsing(format!(
+                            "Failed to parse OpenAI embeddings: {}",
+                            e
+                        ))
+                    })?;
+                // The API doesn't promise to keep the input order
+                list.data.sort_by_key(|entry| entry.index);
+                Ok(EmbeddingResult {
+                    model: list.model,
+                    embeddings: list
+                        .data
+                        .into_iter()
+                        .map(|entry| Embedding(entry.embedding))
+                        .collect(),
+                    usage: list.usage,
+                })
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::providers::http::HttpConfig;
+    use crate::providers::retry::RetryPolicy;
+    use crate::providers::stream::collect_stream;
+    use mockito::Matcher;
+
+    // Config of a provider answering from `server`, for tests of any module.
+    // The mocks answer the same way every time, so errors aren't retried.
+    pub(crate) fn mock_config(server: &mockito::Server, model: &str) -> ProviderConfig {
+        ProviderConfig::new(ApiType::OpenAI, model.to_string())
+            .with_api_key("test-key".to_string())
+            .with_api_base_url(format!("{}/v1", server.url()))
+            .with_retry(RetryPolicy::none())
+    }
+
+    pub(crate) fn mock_provider(server: &mockito::Server, model: &str) -> OpenAiProvider {
+        OpenAiProvider::new(Arc::new(mock_config(server, model))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_stream() {
+        let mut server = mockito::Server::new_async().await;
+        let body = [
+            r#"data: {"choices":[{"index":0,"delta":{"role":"assistant"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"reasoning_content":"Greet"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":"<think>back</think>"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":" world"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            "data: [DONE]",
+        ]
+        .join("\n\n")
+            + "\n\n";
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(body)
+            .create_async()
+            .await;
+
+        let provider = mock_provider(&server, "local-model");
+        let stream = provider
+            .chat_completion_stream(vec![ChatMessage::user("hi")], None, None, None, None)
+            .await
+            .unwrap();
+        let collected = collect_stream(stream).await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(collected.message.content, "Hello world");
+        assert_eq!(collected.message.reasoning.as_deref(), Some("Greetback"));
+        assert_eq!(collected.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn test_http_settings_and_cancellation() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_header("http-referer", "https://example.com")
+            .match_body(Matcher::PartialJson(
+                json!({"provider": {"order": ["groq"]}}),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "hi"}}]})
+                    .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let cancel = tokio_util::sync::CancellationToken::new();
+        let config = mock_config(&server, "llama")
+            .with_http(
+                HttpConfig::default()
+                    .with_connect_timeout(5)
+                    .with_header("HTTP-Referer", "https://example.com")
+                    .with_extra_body("provider", json!({"order": ["groq"]})),
+            )
+            .with_cancellation(cancel.clone());
+        let provider = OpenAiProvider::new(Arc::new(config)).unwrap();
+        let messages = vec![ChatMessage::user("hi")];
+        let answer = provider
+            .chat_completion(messages.clone(), None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(answer, "hi");
+
+        cancel.cancel();
+        let error = provider
+            .chat_completion(messages, None, None, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Cancelled));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_complete_returns_usage_and_finish_reason() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "chatcmpl-7",
+                    "object": "chat.completion",
+                    "created": 1700000000,
+                    "model": "qwen2.5-7b-instruct-1m",
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "fn main() {"},
+                        "finish_reason": "length"
+                    }],
+                    "usage": {"prompt_tokens": 20, "completion_tokens": 5, "total_tokens": 25}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let provider = mock_provider(&server, "qwen");
+        let result = provider
+            .complete(vec![ChatMessage::user("hi")], None, None, Some(5), None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.id, "chatcmpl-7");
+        assert_eq!(result.model, "qwen2.5-7b-instruct-1m");
+        assert!(result.is_truncated());
+        assert_eq!(result.usage.unwrap().completion_tokens, 5);
+        assert_eq!(result.content(), Some("fn main() {"));
+    }
+
+    #[tokio::test]
+    async fn test_sampling_params_are_sent() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(json!({
+                "top_p": 0.5,
+                "top_k": 40,
+                "stop": ["```"],
+                "presence_penalty": 0.5,
+                "frequency_penalty": 0.25,
+                "n": 2,
+                "seed": 7
+            })))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "ok"}}]})
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let config = mock_config(&server, "phi-4")
+            .with_provider_type(ProviderType::LmStudio)
+            .with_top_p(0.5)
+            .with_top_k(40)
+            .with_stop(vec!["```".to_string()])
+            .with_presence_penalty(0.5)
+            .with_frequency_penalty(0.25)
+            .with_n(2)
+            .with_seed(7);
+        let provider = OpenAiProvider::new(Arc::new(config)).unwrap();
+        provider
+            .chat_completion(vec![ChatMessage::user("hi")], None, None, None, None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_list_models() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/v1/models")
+            .with_status(200)
+            .with_body(
+                json!({"object": "list", "data": [
+                    {"id": "qwen2.5-7b-instruct-1m", "object": "model", "owned_by": "organization_owner"},
+                    {"id": "phi-4", "object": "model", "owned_by": "organization_owner"}
+                ]})
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let provider = mock_provider(&server, "phi-4");
+        assert_eq!(
+            provider.list_models().await.unwrap(),
+            vec!["qwen2.5-7b-instruct-1m", "phi-4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_models_uses_ollama_tags() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/tags")
+            .with_status(200)
+            .with_body(
+                json!({"models": [
+                    {"name": "granite3.2:latest", "model": "granite3.2:latest", "size": 1},
+                    {"name": "qwen2.5:7b", "model": "qwen2.5:7b", "size": 2}
+                ]})
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let config = ProviderConfig::new(ApiType::OpenAI, "granite3.2".to_string())
+            .with_provider_type(ProviderType::Ollama)
+            .with_api_key("xxx".to_string())
+            .with_api_base_url(format!("{}/v1", server.url()));
+        let provider = OpenAiProvider::new(Arc::new(config)).unwrap();
+        let models = provider.list_models().await.unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(models, vec!["granite3.2:latest", "qwen2.5:7b"]);
+    }
+
+    #[test]
+    fn test_reasoning_is_not_sent_back() {
+        let mut answer = ChatMessage::assistant("4");
+        answer.reasoning = Some("2 + 2".to_string());
+        let messages = vec![
+            ChatMessage::user("2 + 2?"),
+            answer,
+            ChatMessage::user("3 + 3?"),
+        ];
+        let config = ProviderConfig::new(ApiType::OpenAI, "deepseek-reasoner".to_string())
+            .with_api_key("test-key".to_string());
+
+        let provider = OpenAiProvider::new(Arc::new(config.clone())).unwrap();
+        let body = provider.request_body(messages.clone(), None, None, None, None);
+        assert!(body["messages"][1].get("reasoning_content").is_none());
+
+        let provider = OpenAiProvider::new(Arc::new(config.with_keep_reasoning(true))).unwrap();
+        let body = provider.request_body(messages, None, None, None, None);
+        assert_eq!(body["messages"][1]["reasoning_content"], "2 + 2");
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_tools() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(json!({
+                "tools": [{"type": "function", "function": {"name": "read_file"}}],
+                "messages": [
+                    {"role": "user", "content": "show main.rs"},
+                    {"role": "tool", "content": "fn main() {}", "tool_call_id": "call_0"}
+                ]
+            })))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 1,
+                    "model": "local-model",
+                    "choices": [{
+                        "index": 0,
+                        "finish_reason": "tool_calls",
+                        "message": {
+                            "role": "assistant",
+                            "content": null,
+                            "tool_calls": [{
+                                "id": "call_1",
+                                "type": "function",
+                                "function": {"name": "read_file", "arguments": "{\"file_path\":\"main.rs\"}"}
+                            }]
+                        }
+                    }]
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let provider = mock_provider(&server, "local-model");
+        let tools = vec![ToolDefinition {
+            name: "read_file".to_string(),
+            description: None,
+            parameters: json!({"type": "object"}),
+        }];
+        let message = provider
+            .chat_with_tools(
+                vec![
+                    ChatMessage::user("show main.rs"),
+                    ChatMessage::tool("call_0", "fn main() {}"),
+                ],
+                tools,
+            )
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(message.content, "");
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "read_file");
+    }
+
+    #[test]
+    fn test_parse_chunk_reports_bad_json() {
+        assert!(parse_chunk("[DONE]").is_none());
+        assert!(matches!(
+            parse_chunk("{not json"),
+            Some(Err(ProviderError::ResponseParsing(_)))
+        ));
+    }
+
+    #[test]
+    fn test_parse_chunk_keeps_first_choice() {
+        let chunk = |index: usize, content: &str| {
+            json!({"choices": [{"index": index, "delta": {"content": content}}]}).to_string()
+        };
+        assert!(parse_chunk(&chunk(1, "second")).is_none());
+        assert_eq!(
+            parse_chunk(&chunk(0, "first")).unwrap().unwrap().content,
+            "first"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embed_in_batches() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("POST", "/v1/embeddings")
+            .match_body(Matcher::Json(
+                json!({"model": "text-embedding-3-small", "input": ["a", "b"]}),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({
+                    "data": [
+                        {"index": 1, "embedding": [0.0, 1.0]},
+                        {"index": 0, "embedding": [1.0, 0.0]}
+                    ],
+                    "model": "text-embedding-3-small",
+                    "usage": {"prompt_tokens": 2, "total_tokens": 2}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let second = server
+            .mock("POST", "/v1/embeddings")
+            .match_body(Matcher::Json(
+                json!({"model": "text-embedding-3-small", "input": ["c"]}),
+            ))
+            .with_status(200)
+            .with_body(json!({"data": [{"index": 0, "embedding": [0.5, 0.5]}]}).to_string())
+            .create_async()
+            .await;
+
+        let config = mock_config(&server, "text-embedding-3-small").with_embedding_batch_size(2);
+        let provider = OpenAiProvider::new(Arc::new(config)).unwrap();
+        let result = provider
+            .embed(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+            .await
+            .unwrap();
+
+        first.assert_async().await;
+        second.assert_async().await;
+        assert_eq!(
+            result.embeddings,
+            vec![
+                Embedding(vec![1.0, 0.0]),
+                Embedding(vec![0.0, 1.0]),
+                Embedding(vec![0.5, 0.5])
+            ]
+        );
+        assert_eq!(result.model, "text-embedding-3-small");
+        assert_eq!(result.usage.unwrap().total_tokens, 2);
     }
 }
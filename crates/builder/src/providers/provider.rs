@@ -1,28 +1,51 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::string::ToString;
 use std::sync::Arc;
 use strum_macros::Display;
 use strum_macros::EnumString;
 use strum_macros::IntoStaticStr;
+use tokio_util::sync::CancellationToken;
 
+use super::anthropic::AnthropicProvider;
+use super::api_key::ApiKey;
+use super::cache::{CacheConfig, CachedProvider, ResponseCache};
+use super::cassette::Cassette;
+use super::catalog::ModelCapabilities;
+use super::chat::ChatProvider;
 use super::config::{ProviderConfig, ProviderError};
-use super::models::Models;
+use super::embedding::EmbeddingProvider;
+use super::http::HttpConfig;
+use super::models::{Models, StructuredOutput};
+use super::ollama::OllamaProvider;
 use super::openai::OpenAiProvider;
+use super::retry::RetryPolicy;
+use super::schema_prompt::SchemaPromptProvider;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
 pub struct Provider {
     pub name: String,
     pub base_url: String,
-    pub api_key: String,
+    // Literal key or a reference like `env:OPENAI_API_KEY`, see ApiKey
+    #[serde(default)]
+    pub api_key: ApiKey,
     pub provider_type: ProviderType,
     pub api_type: ApiType,
     pub models: Vec<Models>,
     pub default_model: Models,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    // Responses are cached on disk when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpConfig>,
 }
 
 #[derive(
     Serialize,
     Deserialize,
+    JsonSchema,
     Debug,
     PartialEq,
     Default,
@@ -48,6 +71,7 @@ pub enum ProviderType {
 #[derive(
     Serialize,
     Deserialize,
+    JsonSchema,
     Debug,
     PartialEq,
     Default,
@@ -61,6 +85,36 @@ pub enum ApiType {
     Anthropic,
     #[default]
     OpenAI,
+    // Ollama's native /api/chat, needed for options like num_ctx
+    Ollama,
+}
+
+// What changed in a provider's model list after a sync
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ModelSync {
+    pub added: Vec<String>,
+    // Models that disappeared from the server since the last sync
+    pub missing: Vec<String>,
+}
+
+impl ModelSync {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.missing.is_empty()
+    }
+}
+
+// Ollama treats a name without a tag as `:latest`. A tag follows the last
+// colon of the last path segment, `localhost:5000/phi4` has none.
+fn same_model(a: &str, b: &str) -> bool {
+    fn with_tag(name: &str) -> String {
+        let last = name.rsplit('/').next().unwrap_or(name);
+        if last.contains(':') {
+            name.to_string()
+        } else {
+            format!("{}:latest", name)
+        }
+    }
+    a == b || with_tag(a) == with_tag(b)
 }
 
 // A builder struct for configuring and executing requests to a model
@@ -72,7 +126,22 @@ pub struct ModelBuilder {
     ctx_size: Option<u16>,
     top_p: Option<f32>,
     top_k: Option<u16>,
+    stop: Option<Vec<String>>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    n: Option<u32>,
+    seed: Option<i64>,
+    repeat_penalty: Option<f32>,
+    mirostat: Option<u8>,
+    keep_alive: Option<String>,
     schema: Option<String>,
+    embedding_batch_size: Option<usize>,
+    retry: Option<RetryPolicy>,
+    cassette: Option<Arc<Cassette>>,
+    cache: Option<CacheConfig>,
+    http: Option<HttpConfig>,
+    cancel: Option<CancellationToken>,
+    keep_reasoning: bool,
     // Add other parameters as needed
 }
 
@@ -106,43 +175,219 @@ impl ModelBuilder {
         self.top_k = Some(top_k);
         self
     }
+
+    // Set sequences that stop the generation
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    // Set the number of choices to generate
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    // Set the seed for reproducible sampling
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    // Set the mirostat mode, Ollama only
+    pub fn with_mirostat(mut self, mirostat: u8) -> Self {
+        self.mirostat = Some(mirostat);
+        self
+    }
+
+    // Set how long Ollama keeps the model loaded, e.g. "10m" or "-1"
+    pub fn with_keep_alive(mut self, keep_alive: impl Into<String>) -> Self {
+        self.keep_alive = Some(keep_alive.into());
+        self
+    }
+
     pub fn with_schema(mut self, schema: String) -> Self {
         self.schema = Some(schema);
         self
     }
 
+    // Set how many texts are embedded per request
+    pub fn with_embedding_batch_size(mut self, batch_size: usize) -> Self {
+        self.embedding_batch_size = Some(batch_size);
+        self
+    }
+
+    // Set the retry policy, overriding the one from the provider config
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // Record or replay the HTTP traffic of the built provider
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    // Replaces the provider's `http` settings
+    pub fn with_http(mut self, http: HttpConfig) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    // Requests and streams of the built provider end with
+    // ProviderError::Cancelled once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Send the reasoning of earlier answers back instead of dropping it
+    pub fn with_keep_reasoning(mut self, keep: bool) -> Self {
+        self.keep_reasoning = keep;
+        self
+    }
+
+    // Cache completions on disk, overriding the cache settings of the provider
+    pub fn with_cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    // Collect the provider and model settings into a ProviderConfig. Values
+    // set on the builder win over the model params from the config file.
+    fn provider_config(&self) -> Arc<ProviderConfig> {
+        let params = self.model.params.clone().unwrap_or_default();
+        let capabilities = self.capabilities();
+        Arc::new(ProviderConfig {
+            api_type: self.provider.api_type,
+            api_key: Some(self.provider.api_key.clone()),
+            api_base_url: Some(self.provider.base_url.clone()),
+            model: self.model.model.clone(),
+            provider_type: self.provider.provider_type,
+            temperature: self.temperature.or(params.temperature),
+            max_tokens: self
+                .max_tokens
+                .or_else(|| params.max_tokens.map(|t| t as u16))
+                .map(|t| match capabilities.and_then(|c| c.max_output) {
+                    // Asking for more than the model can write is an error on most APIs
+                    Some(max) => t.min(u16::try_from(max).unwrap_or(u16::MAX)),
+                    None => t,
+                }),
+            top_p: self.top_p.or(params.top_p),
+            top_k: self
+                .top_k
+                .map(u32::from)
+                .or_else(|| params.top_k.map(|k| k as u32)),
+            ctx_size: self
+                .ctx_size
+                .map(u32::from)
+                .or_else(|| params.ctx.map(|c| c as u32)),
+            stop: self.stop.clone().or_else(|| params.stop.map(|s| vec![s])),
+            presence_penalty: self.presence_penalty.or(params.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(params.frequency_penalty),
+            n: self.n.or_else(|| params.n.map(|n| n as u32)),
+            seed: self.seed.or(params.seed),
+            repeat_penalty: self.repeat_penalty.or(params.repeat_penalty),
+            mirostat: self.mirostat.or(params.mirostat),
+            mirostat_tau: params.mirostat_tau,
+            mirostat_eta: params.mirostat_eta,
+            keep_alive: self.keep_alive.clone().or(params.keep_alive),
+            schema: self.schema.clone(),
+            embedding_batch_size: self.embedding_batch_size,
+            retry: self
+                .retry
+                .clone()
+                .or_else(|| self.provider.retry.clone())
+                .unwrap_or_default(),
+            cassette: self.cassette.clone(),
+            http: self
+                .http
+                .clone()
+                .or_else(|| self.provider.http.clone())
+                .unwrap_or_default(),
+            cancel: self.cancel.clone(),
+            reasoning: capabilities.is_some_and(|c| c.reasoning),
+            keep_reasoning: self.keep_reasoning,
+            // Add other parameters as needed
+        })
+    }
+
+    // Tokens prompt and answer must fit in: `with_ctx`, the `ctx` model
+    // param or the catalog, in that order
+    pub fn context_window(&self) -> Option<u32> {
+        self.ctx_size
+            .map(u32::from)
+            .or_else(|| self.model.params.as_ref()?.ctx.map(|c| c as u32))
+            .or_else(|| self.capabilities().map(|c| c.context_window))
+    }
+
+    // Longest answer requests will ask for
+    pub fn max_tokens(&self) -> Option<u16> {
+        self.provider_config().max_tokens
+    }
+
+    // What the model accepts, None for custom models without `capabilities`
+    pub fn capabilities(&self) -> Option<ModelCapabilities> {
+        self.model.known_capabilities()
+    }
+
+    // Models the catalog knows to lack native schema support get the schema
+    // through the prompt
+    fn structured_output(&self) -> StructuredOutput {
+        match self.capabilities() {
+            Some(capabilities) if !capabilities.json_schema => StructuredOutput::Prompt,
+            _ => self.model.structured_output,
+        }
+    }
+
     // Build the provider based on the API type
-    pub fn build(&self) -> Result<OpenAiProvider, ProviderError> {
+    pub fn build(&self) -> Result<Box<dyn ChatProvider>, ProviderError> {
+        let config = self.provider_config();
+        config.validate()?;
+        let provider: Box<dyn ChatProvider> = match self.provider.api_type {
+            ApiType::OpenAI => Box::new(OpenAiProvider::new(config.clone())?),
+            ApiType::Anthropic => Box::new(AnthropicProvider::new(config.clone())?),
+            ApiType::Ollama => Box::new(OllamaProvider::new(config.clone())?),
+        };
+        let provider: Box<dyn ChatProvider> = match self.structured_output() {
+            StructuredOutput::Native => provider,
+            StructuredOutput::Prompt => Box::new(SchemaPromptProvider::new(provider)),
+        };
+        match self.cache.as_ref().or(self.provider.cache.as_ref()) {
+            Some(cache) => Ok(Box::new(CachedProvider::new(
+                provider,
+                ResponseCache::new(cache)?,
+                config.cache_context(),
+            ))),
+            None => Ok(provider),
+        }
+    }
+
+    // Build an embedding client for the model. Responses are not cached.
+    pub fn build_embedder(&self) -> Result<Box<dyn EmbeddingProvider>, ProviderError> {
+        let config = self.provider_config();
+        config.validate()?;
         match self.provider.api_type {
-            ApiType::OpenAI => {
-                // Create a provider config
-                let config = Arc::new(ProviderConfig {
-                    api_type: self.provider.api_type,
-                    api_key: Some(self.provider.api_key.clone()),
-                    api_base_url: Some(self.provider.base_url.clone()),
-                    model: self.model.model.clone(),
-                    temperature: self
-                        .temperature
-                        .or_else(|| self.model.params.as_ref().and_then(|p| p.temperature)),
-                    max_tokens: self.max_tokens.or_else(|| {
-                        self.model
-                            .params
-                            .as_ref()
-                            .and_then(|p| p.max_tokens.map(|t| t as u16))
-                    }),
-                    schema: self.schema.clone(),
-                    // Add other parameters as needed
-                });
-
-                // Create the OpenAI provider
-                OpenAiProvider::new(config)
-            }
-            ApiType::Anthropic => {
-                // For future implementation
-                Err(ProviderError::Configuration(
-                    "Anthropic API not yet implemented".to_string(),
-                ))
-            }
+            ApiType::OpenAI => Ok(Box::new(OpenAiProvider::new(config)?)),
+            ApiType::Ollama => Ok(Box::new(OllamaProvider::new(config)?)),
+            ApiType::Anthropic => Err(ProviderError::Configuration(
+                "The Anthropic API has no embeddings endpoint".to_string(),
+            )),
         }
     }
 }
@@ -150,30 +395,51 @@ impl ModelBuilder {
 impl Provider {
     pub fn provider(provider_type: ProviderType) -> Self {
         match provider_type {
+            ProviderType::Anthropic => Self {
+                name: ProviderType::Anthropic.to_string(),
+                base_url: "https://api.anthropic.com/v1".to_string(),
+                api_key: ApiKey::default(),
+                provider_type: ProviderType::Anthropic,
+                models: vec![
+                    "claude-3-7-sonnet-latest".into(),
+                    "claude-3-5-sonnet-latest".into(),
+                    "claude-3-5-haiku-latest".into(),
+                ],
+                default_model: "claude-3-5-haiku-latest".into(),
+                api_type: ApiType::Anthropic,
+                retry: None,
+                cache: None,
+                http: None,
+            },
             ProviderType::OpenAI => Self {
                 name: ProviderType::OpenAI.to_string(),
                 base_url: "https://api.openai.com/v1".to_string(),
-                api_key: "".to_string(),
+                api_key: ApiKey::default(),
                 provider_type: ProviderType::OpenAI,
                 models: vec!["gpt4".into()],
                 default_model: "gpt4".into(),
                 api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
             },
             ProviderType::Ollama => Self {
                 name: ProviderType::Ollama.to_string(),
-                base_url: "http://127.0.0.1:1143/v1".to_string(),
-                api_key: "xxx".to_string(),
+                base_url: "http://127.0.0.1:11434".to_string(),
+                api_key: "xxx".into(),
                 provider_type: ProviderType::Ollama,
                 models: vec!["granite3.2".into(), "qwen2.5".into(), "gemma3".into()],
                 default_model: "granite3.2".into(),
-                api_type: ApiType::OpenAI,
+                api_type: ApiType::Ollama,
+                retry: None,
+                cache: None,
+                http: None,
             },
             ProviderType::LmStudio => Self {
                 name: ProviderType::LmStudio.to_string(),
                 base_url: "http://127.0.0.1:1234/v1".to_string(),
-                api_key: "xxx".to_string(),
-                provider_type: ProviderType::Ollama,
-
+                api_key: "xxx".into(),
+                provider_type: ProviderType::LmStudio,
                 models: vec![
                     "damienclere/granite-3.2-2b-instruct-4bit".into(),
                     "bartowski/Tesslate_Gradience-T1-3B-preview-GGUF".into(),
@@ -191,7 +457,6 @@ impl Provider {
                     "qwen2.5-7b-instruct-1m".into(),
                     "qwq-coder-instruct-mlx".into(),
                     "fuseo1-deepseekr1-qwen2.5-coder-14b-preview-mixed_3_6".into(),
-                    "alexgusevski/Selene-1-Mini-Llama-3.1-8B-q3-mlx".into(),
                     "reasoning-ties-coder-v1.1".into(),
                     "lmstudio-community/internlm3-8b-instruct".into(),
                     "auto-rag-llama-3-8b-instruct-i1".into(),
@@ -203,20 +468,96 @@ impl Provider {
                 ],
                 default_model: "granite3.2".into(),
                 api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
+            },
+            // The hosted OpenAI compatible APIs read their key from the
+            // environment by default, set `api_key` to override it
+            ProviderType::Groq => Self {
+                name: ProviderType::Groq.to_string(),
+                base_url: "https://api.groq.com/openai/v1".to_string(),
+                api_key: "env:GROQ_API_KEY".into(),
+                provider_type: ProviderType::Groq,
+                models: vec![
+                    "llama-3.3-70b-versatile".into(),
+                    "llama-3.1-8b-instant".into(),
+                    "qwen/qwen3-32b".into(),
+                ],
+                default_model: "llama-3.1-8b-instant".into(),
+                api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
+            },
+            ProviderType::Cerebras => Self {
+                name: ProviderType::Cerebras.to_string(),
+                base_url: "https://api.cerebras.ai/v1".to_string(),
+                api_key: "env:CEREBRAS_API_KEY".into(),
+                provider_type: ProviderType::Cerebras,
+                models: vec![
+                    "llama-3.3-70b".into(),
+                    "llama3.1-8b".into(),
+                    "qwen-3-32b".into(),
+                ],
+                default_model: "llama3.1-8b".into(),
+                api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
+            },
+            ProviderType::DeepSeek => Self {
+                name: ProviderType::DeepSeek.to_string(),
+                base_url: "https://api.deepseek.com/v1".to_string(),
+                api_key: "env:DEEPSEEK_API_KEY".into(),
+                provider_type: ProviderType::DeepSeek,
+                models: vec!["deepseek-chat".into(), "deepseek-reasoner".into()],
+                default_model: "deepseek-chat".into(),
+                api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
+            },
+            ProviderType::XAI => Self {
+                name: ProviderType::XAI.to_string(),
+                base_url: "https://api.x.ai/v1".to_string(),
+                api_key: "env:XAI_API_KEY".into(),
+                provider_type: ProviderType::XAI,
+                models: vec!["grok-3".into(), "grok-3-mini".into(), "grok-4".into()],
+                default_model: "grok-3-mini".into(),
+                api_type: ApiType::OpenAI,
+                retry: None,
+                cache: None,
+                http: None,
             },
-            // ProviderType::Llamafile => Self::provider_llamafile(),
-            _ => Self::default(),
+            // A llamafile serves the single model it was built with, under
+            // whatever name the request asks for. Its server may ignore
+            // `response_format: json_schema`.
+            ProviderType::Llamafile => {
+                let model = Models {
+                    structured_output: StructuredOutput::Prompt,
+                    ..Models::from("LLaMA_CPP")
+                };
+                Self {
+                    name: ProviderType::Llamafile.to_string(),
+                    base_url: "http://127.0.0.1:8080/v1".to_string(),
+                    api_key: "no-key".into(),
+                    provider_type: ProviderType::Llamafile,
+                    models: vec![model.clone()],
+                    default_model: model,
+                    api_type: ApiType::OpenAI,
+                    retry: None,
+                    cache: None,
+                    http: None,
+                }
+            }
         }
     }
 
     // Create a ModelBuilder with the specified model
     pub fn with_model(&self, model_name: &str) -> Option<ModelBuilder> {
         // Find the model
-        let custom_model = Models {
-            name: model_name.to_string(),
-            model: model_name.to_string(),
-            params: None,
-        };
+        let custom_model = Models::from(model_name);
         let model = self
             .models
             .iter()
@@ -233,10 +574,57 @@ impl Provider {
             ctx_size: None,
             top_p: None,
             top_k: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            n: None,
+            seed: None,
+            repeat_penalty: None,
+            mirostat: None,
+            keep_alive: None,
             schema: None,
+            embedding_batch_size: None,
+            retry: None,
+            cassette: None,
+            cache: None,
+            http: None,
+            cancel: None,
+            keep_reasoning: false,
         })
     }
 
+    // Merge the model ids reported by the server into `models`. New models are
+    // appended, known ones keep their params and models the server no longer
+    // lists are flagged as missing instead of being removed.
+    pub fn merge_models(&mut self, discovered: &[String]) -> ModelSync {
+        let mut sync = ModelSync::default();
+        for model in &mut self.models {
+            let present = discovered.iter().any(|id| same_model(id, &model.model));
+            if !present && !model.missing {
+                sync.missing.push(model.model.clone());
+            }
+            model.missing = !present;
+        }
+        for id in discovered {
+            if !self.models.iter().any(|m| same_model(&m.model, id)) {
+                self.models.push(id.clone().into());
+                sync.added.push(id.clone());
+            }
+        }
+        sync
+    }
+
+    // The model marked with `embedding = true`
+    pub fn embedding_model(&self) -> Option<&Models> {
+        self.models.iter().find(|m| m.embedding)
+    }
+
+    // Create a ModelBuilder with the embedding model, if one is marked
+    pub fn with_embedding_model(&self) -> Option<ModelBuilder> {
+        self.embedding_model()
+            .and_then(|model| self.with_model(&model.name))
+    }
+
     // Create a ModelBuilder with the default model
     pub fn with_default_model(&self) -> ModelBuilder {
         ModelBuilder {
@@ -247,7 +635,22 @@ impl Provider {
             ctx_size: None,
             top_p: None,
             top_k: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            n: None,
+            seed: None,
+            repeat_penalty: None,
+            mirostat: None,
+            keep_alive: None,
             schema: None,
+            embedding_batch_size: None,
+            retry: None,
+            cassette: None,
+            cache: None,
+            http: None,
+            cancel: None,
+            keep_reasoning: false,
         }
     }
 }
@@ -257,3 +660,181 @@ impl Default for Provider {
         Provider::provider(ProviderType::OpenAI)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::models::ModelParams;
+
+    #[test]
+    fn test_builder_params_override_model_params() {
+        let mut provider = Provider::provider(ProviderType::OpenAI);
+        provider.models = vec![Models {
+            name: "gpt4".to_string(),
+            model: "gpt4".to_string(),
+            params: Some(ModelParams {
+                temperature: Some(0.7),
+                top_p: Some(0.9),
+                stop: Some("END".to_string()),
+                seed: Some(1),
+                ..ModelParams::default()
+            }),
+            missing: false,
+            embedding: false,
+            structured_output: StructuredOutput::Native,
+            capabilities: None,
+        }];
+
+        let config = provider
+            .with_model("gpt4")
+            .unwrap()
+            .with_temperature(0.1)
+            .with_seed(42)
+            .provider_config();
+        assert_eq!(config.temperature, Some(0.1));
+        assert_eq!(config.top_p, Some(0.9));
+        assert_eq!(config.stop, Some(vec!["END".to_string()]));
+        assert_eq!(config.seed, Some(42));
+        assert_eq!(config.provider_type, ProviderType::OpenAI);
+    }
+
+    #[test]
+    fn test_merge_models() {
+        let mut provider = Provider::provider(ProviderType::OpenAI);
+        provider.models = vec!["phi-4".into(), "gone".into()];
+        provider.models[0].params = Some(ModelParams {
+            temperature: Some(0.2),
+            ..ModelParams::default()
+        });
+
+        let discovered = vec!["phi-4".to_string(), "qwen2.5-7b-instruct-1m".to_string()];
+        let sync = provider.merge_models(&discovered);
+        assert_eq!(sync.added, vec!["qwen2.5-7b-instruct-1m".to_string()]);
+        assert_eq!(sync.missing, vec!["gone".to_string()]);
+        assert_eq!(provider.models.len(), 3);
+        assert!(provider.models[0].params.is_some());
+        assert!(!provider.models[0].missing);
+        assert!(provider.models[1].missing);
+
+        // Already flagged models are not reported again, returning ones are unflagged
+        let sync = provider.merge_models(&discovered);
+        assert_eq!(sync, ModelSync::default());
+        let sync = provider.merge_models(&["gone".to_string()]);
+        assert_eq!(sync.missing.len(), 2);
+        assert!(!provider.models[1].missing);
+
+        // Ollama lists `llama3.2` as `llama3.2:latest`
+        let mut provider = Provider::provider(ProviderType::Ollama);
+        provider.models = vec!["llama3.2".into(), "qwen3:8b".into()];
+        let sync = provider.merge_models(&[
+            "llama3.2:latest".to_string(),
+            "qwen3:8b".to_string(),
+            "qwen3:latest".to_string(),
+        ]);
+        assert_eq!(sync.added, vec!["qwen3:latest".to_string()]);
+        assert!(sync.missing.is_empty());
+        assert!(same_model(
+            "localhost:5000/phi4",
+            "localhost:5000/phi4:latest"
+        ));
+    }
+
+    #[test]
+    fn test_embedding_model() {
+        let mut provider = Provider::provider(ProviderType::Ollama);
+        assert!(provider.with_embedding_model().is_none());
+        provider.models.push(Models {
+            embedding: true,
+            .."nomic-embed-text".into()
+        });
+        let builder = provider.with_embedding_model().unwrap();
+        assert_eq!(builder.model.model, "nomic-embed-text");
+        assert_eq!(
+            builder.build_embedder().unwrap().model(),
+            "nomic-embed-text"
+        );
+        assert!(
+            Provider::provider(ProviderType::Anthropic)
+                .with_default_model()
+                .build_embedder()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_presets() {
+        for provider_type in [
+            ProviderType::Groq,
+            ProviderType::Cerebras,
+            ProviderType::DeepSeek,
+            ProviderType::XAI,
+            ProviderType::Llamafile,
+        ] {
+            let provider = Provider::provider(provider_type);
+            assert_eq!(provider.provider_type, provider_type);
+            assert_eq!(provider.name, provider_type.to_string());
+            assert!(provider.base_url.ends_with("/v1"));
+            assert!(
+                provider
+                    .models
+                    .iter()
+                    .any(|m| m.name == provider.default_model.name)
+            );
+            assert!(
+                provider
+                    .with_default_model()
+                    .provider_config()
+                    .validate()
+                    .is_ok()
+            );
+        }
+        assert_eq!(
+            Provider::provider(ProviderType::Groq).api_key.reference(),
+            "env:GROQ_API_KEY"
+        );
+    }
+
+    #[test]
+    fn test_catalog_picks_request_features() {
+        let mut provider = Provider::provider(ProviderType::OpenAI);
+        provider.models = vec!["gpt-4-0613".into(), "gpt-4o".into(), "phi-4".into()];
+
+        let gpt4 = provider
+            .with_model("gpt-4-0613")
+            .unwrap()
+            .with_max_tokens(10_000);
+        assert_eq!(gpt4.provider_config().max_tokens, Some(8_192));
+        assert_eq!(gpt4.structured_output(), StructuredOutput::Prompt);
+        let gpt4o = provider.with_model("gpt-4o").unwrap();
+        assert_eq!(gpt4o.structured_output(), StructuredOutput::Native);
+        assert!(gpt4o.capabilities().unwrap().vision);
+
+        let phi = provider
+            .with_model("phi-4")
+            .unwrap()
+            .with_max_tokens(10_000);
+        assert_eq!(phi.capabilities(), None);
+        assert_eq!(phi.provider_config().max_tokens, Some(10_000));
+        provider.models[2].capabilities = Some(ModelCapabilities {
+            context_window: 16_384,
+            tools: true,
+            ..ModelCapabilities::default()
+        });
+        let phi = provider.with_model("phi-4").unwrap();
+        assert_eq!(phi.structured_output(), StructuredOutput::Prompt);
+
+        let llamafile = Provider::provider(ProviderType::Llamafile).with_default_model();
+        assert_eq!(llamafile.structured_output(), StructuredOutput::Prompt);
+    }
+
+    #[test]
+    fn test_build_rejects_unsupported_params() {
+        let provider = Provider::provider(ProviderType::OpenAI);
+        let error = provider
+            .with_default_model()
+            .with_ctx(8192)
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().contains("does not support: ctx"));
+    }
+}
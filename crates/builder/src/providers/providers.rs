@@ -1,55 +1,213 @@
+use schemars::{JsonSchema, schema_for};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use super::provider::Provider;
+use super::config::ProviderError;
 use super::provider::ProviderType;
+use super::provider::{ModelSync, Provider};
+use super::router::{Route, RouteStrategy, RoutedProvider};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone)]
 pub struct Providers {
     pub providers: Vec<Provider>,
     pub default_provider: String,
+    // Named provider/model chains callers can ask for instead of a provider
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
 }
-impl Providers {
-    pub fn get_config_path() -> PathBuf {
-        // Set environment variable for config path if not already set
-        if std::env::var("CONFIG_ROOT_DIR").is_err() {
-            panic!("CONFIG_ROOT_DIR environment variable is not set.");
+
+// Why providers.toml could not be loaded or saved
+#[derive(Debug)]
+pub enum ConfigError {
+    // CONFIG_ROOT_DIR is not set
+    MissingRootDir,
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    // The file is not valid TOML or doesn't match the Providers layout
+    Parse {
+        path: PathBuf,
+        message: String,
+        // Byte range of the offending part of the file
+        span: Option<Range<usize>>,
+        // 1-based line and column of the start of the span
+        location: Option<(usize, usize)>,
+    },
+    Serialize {
+        path: PathBuf,
+        message: String,
+    },
+    // The file parsed but its content doesn't make sense
+    Invalid {
+        path: PathBuf,
+        problems: Vec<String>,
+    },
+}
+
+impl ConfigError {
+    fn parse(path: &Path, content: &str, error: toml::de::Error) -> Self {
+        let span = error.span();
+        let location = span.as_ref().map(|span| {
+            let before = &content[..span.start.min(content.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            (line, column)
+        });
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            message: error.message().to_string(),
+            span,
+            location,
         }
+    }
+}
 
-        PathBuf::from(std::env::var("CONFIG_ROOT_DIR").unwrap()).join("providers.toml")
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingRootDir => {
+                write!(f, "CONFIG_ROOT_DIR environment variable is not set")
+            }
+            ConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse {
+                path,
+                message,
+                location: Some((line, column)),
+                ..
+            } => write!(f, "{}:{}:{}: {}", path.display(), line, column, message),
+            ConfigError::Parse { path, message, .. } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            ConfigError::Serialize { path, message } => {
+                write!(
+                    f,
+                    "{}: cannot serialize config: {}",
+                    path.display(),
+                    message
+                )
+            }
+            ConfigError::Invalid { path, problems } => {
+                write!(f, "{}: {}", path.display(), problems.join("; "))
+            }
+        }
     }
+}
 
-    pub fn save(&self) {
-        let cfg_path = Self::get_config_path();
-        fs::create_dir_all(cfg_path.parent().unwrap()).expect("Cannot create directory.");
-        toml::to_string(self)
-            .map(|s| {
-                let mut buffer = File::create(&cfg_path).expect("Cannot create file.");
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
 
-                buffer
-                    .write_all(s.as_bytes())
-                    .expect("Unable to write file");
-            })
-            .unwrap();
+impl Providers {
+    pub fn get_config_path() -> Result<PathBuf, ConfigError> {
+        std::env::var("CONFIG_ROOT_DIR")
+            .map(|root| PathBuf::from(root).join("providers.toml"))
+            .map_err(|_| ConfigError::MissingRootDir)
+    }
+
+    // JSON Schema of providers.toml, for editors and config validation
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::to_value(schema_for!(Self)).unwrap_or_default()
     }
 
-    pub fn load() -> Self {
-        let cfg_path = Self::get_config_path();
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&Self::get_config_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), ConfigError> {
+        let content = toml::to_string(self).map_err(|e| ConfigError::Serialize {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        let io_error = |source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::write(path, content).map_err(io_error)
+    }
+
+    // Load providers.toml from CONFIG_ROOT_DIR, writing the default config
+    // there first if the file doesn't exist yet
+    pub fn load() -> Result<Self, ConfigError> {
+        let cfg_path = Self::get_config_path()?;
         if cfg_path.exists() {
-            let content = std::fs::read_to_string(cfg_path).expect("Unable to read file");
-            toml::from_str(&content).unwrap()
+            Self::load_from(&cfg_path)
+        } else {
+            let inst = Self::default_config();
+            inst.save_to(&cfg_path)?;
+            Ok(inst)
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let providers: Self =
+            toml::from_str(&content).map_err(|e| ConfigError::parse(path, &content, e))?;
+        let problems = providers.problems();
+        if problems.is_empty() {
+            Ok(providers)
         } else {
-            let default = Provider::provider(ProviderType::LmStudio);
-            let inst = Self {
-                providers: vec![default.clone(), Provider::provider(ProviderType::Ollama)],
-                default_provider: default.name.clone(),
-            };
-            inst.save();
-            inst
+            Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                problems,
+            })
+        }
+    }
+
+    fn default_config() -> Self {
+        let default = Provider::provider(ProviderType::LmStudio);
+        let ollama = Provider::provider(ProviderType::Ollama);
+        let coder = Route::new("coder", RouteStrategy::Fallback)
+            .with_timeout(30)
+            .with_target(&default.name, &default.default_model.model)
+            .with_target(&ollama.name, &ollama.default_model.model);
+        Self {
+            providers: vec![default.clone(), ollama],
+            default_provider: default.name.clone(),
+            routes: vec![coder],
+        }
+    }
+
+    // Everything wrong with the config, empty when it is fine
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.get_default().is_none() {
+            problems.push(format!(
+                "default_provider {} is not one of the providers",
+                self.default_provider
+            ));
         }
+        for provider in &self.providers {
+            if provider.base_url.trim().is_empty() {
+                problems.push(format!("Provider {} has an empty base_url", provider.name));
+            }
+            let mut names: Vec<&str> = Vec::new();
+            for model in &provider.models {
+                if names.contains(&model.name.as_str()) {
+                    problems.push(format!(
+                        "Provider {} lists model {} more than once",
+                        provider.name, model.name
+                    ));
+                } else {
+                    names.push(&model.name);
+                }
+            }
+        }
+        problems
     }
 
     // Get a provider by its name
@@ -64,8 +222,185 @@ impl Providers {
             .find(|provider| provider.name == name)
     }
 
+    // Register the preset for `provider_type` unless a provider with its name
+    // already exists, and return it for further tweaks. Call `save` to persist.
+    pub fn add_preset(&mut self, provider_type: ProviderType) -> &mut Provider {
+        let preset = Provider::provider(provider_type);
+        let index = match self.providers.iter().position(|p| p.name == preset.name) {
+            Some(index) => index,
+            None => {
+                self.providers.push(preset);
+                self.providers.len() - 1
+            }
+        };
+        &mut self.providers[index]
+    }
+
     // Convenience method to get the default provider
     pub fn get_default(&self) -> Option<&Provider> {
         self.get_by_name(&self.default_provider)
     }
+
+    // Ask the server behind `name` which models it currently serves and merge
+    // them into the provider's model list. Call `save` to persist the result.
+    pub async fn sync_models(&mut self, name: &str) -> Result<ModelSync, ProviderError> {
+        let provider = self
+            .get_by_name_mut(name)
+            .ok_or_else(|| ProviderError::Configuration(format!("Provider {} not found", name)))?;
+        let discovered = provider.with_default_model().build()?.list_models().await?;
+        Ok(provider.merge_models(&discovered))
+    }
+
+    // Get a route by its name
+    pub fn get_route(&self, name: &str) -> Option<&Route> {
+        self.routes.iter().find(|route| route.name == name)
+    }
+
+    // Build a provider that sends every call through the targets of the named route
+    pub fn route(&self, name: &str) -> Result<RoutedProvider, ProviderError> {
+        let route = self
+            .get_route(name)
+            .ok_or_else(|| ProviderError::Configuration(format!("Route {} not found", name)))?;
+        let targets = route
+            .targets
+            .iter()
+            .map(|target| {
+                let provider = self.get_by_name(&target.provider).ok_or_else(|| {
+                    ProviderError::Configuration(format!(
+                        "Route {} uses unknown provider {}",
+                        name, target.provider
+                    ))
+                })?;
+                provider
+                    .with_model(&target.model)
+                    .ok_or_else(|| {
+                        ProviderError::Configuration(format!(
+                            "Route {} uses unknown model {}",
+                            name, target.model
+                        ))
+                    })?
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        RoutedProvider::new(route, targets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::chat::ChatProvider;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_route() {
+        let lm_studio = Provider::provider(ProviderType::LmStudio);
+        let providers = Providers {
+            providers: vec![lm_studio.clone()],
+            default_provider: lm_studio.name.clone(),
+            routes: vec![
+                Route::new("coder", RouteStrategy::Fallback).with_target("LmStudio", "phi-4"),
+                Route::new("broken", RouteStrategy::Fallback).with_target("Nowhere", "phi-4"),
+            ],
+        };
+        assert_eq!(providers.route("coder").unwrap().model(), "phi-4");
+        assert!(
+            providers
+                .route("broken")
+                .unwrap_err()
+                .to_string()
+                .contains("unknown provider Nowhere")
+        );
+        assert!(providers.route("writer").is_err());
+    }
+
+    #[test]
+    fn test_add_preset() {
+        let mut providers = Providers::default_config();
+        let count = providers.providers.len();
+        providers.add_preset(ProviderType::Groq).api_key = "gsk-test".into();
+        let groq = providers.add_preset(ProviderType::Groq);
+        assert_eq!(groq.base_url, "https://api.groq.com/openai/v1");
+        assert_eq!(groq.api_key, "gsk-test".into());
+        assert_eq!(providers.providers.len(), count + 1);
+        assert!(providers.problems().is_empty());
+    }
+
+    #[test]
+    fn test_api_key_reference_is_saved() {
+        let mut groq = Provider::provider(ProviderType::OpenAI);
+        groq.api_key = "env:GROQ_API_KEY".into();
+        let providers = Providers {
+            providers: vec![groq.clone()],
+            default_provider: groq.name.clone(),
+            routes: Vec::new(),
+        };
+        let content = toml::to_string(&providers).unwrap();
+        assert!(content.contains(r#"api_key = "env:GROQ_API_KEY""#));
+        let loaded: Providers = toml::from_str(&content).unwrap();
+        assert_eq!(loaded.providers[0].api_key, groq.api_key);
+
+        groq.api_key = "sk-secret".into();
+        assert!(!format!("{:?}", groq).contains("sk-secret"));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let tmp_dir = TempDir::new("providers-test").unwrap();
+        let path = tmp_dir.path().join("providers.toml");
+
+        std::fs::write(&path, "default_provider = \"LmStudio\"\nproviders = 3\n").unwrap();
+        let error = Providers::load_from(&path).unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::Parse {
+                location: Some((2, 13)),
+                ..
+            }
+        ));
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("{}:2:13: ", path.display()))
+        );
+
+        let mut lm_studio = Provider::provider(ProviderType::LmStudio);
+        lm_studio.base_url = "".to_string();
+        lm_studio.models.push(lm_studio.models[0].clone());
+        let providers = Providers {
+            providers: vec![lm_studio],
+            default_provider: "Nowhere".to_string(),
+            routes: Vec::new(),
+        };
+        providers.save_to(&path).unwrap();
+        let ConfigError::Invalid { problems, .. } = Providers::load_from(&path).unwrap_err() else {
+            panic!("expected validation problems");
+        };
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].contains("default_provider Nowhere"));
+        assert!(problems[1].contains("empty base_url"));
+        assert!(problems[2].contains("more than once"));
+
+        let missing = tmp_dir.path().join("missing.toml");
+        assert!(matches!(
+            Providers::load_from(&missing),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(Providers::default_config().problems().is_empty());
+        let content = std::fs::read_to_string(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../config/providers.toml"
+        ))
+        .unwrap();
+        let providers: Providers = toml::from_str(&content).unwrap();
+        assert_eq!(providers.problems(), Vec::<String>::new());
+
+        let schema = Providers::json_schema();
+        assert!(schema["properties"]["providers"].is_object());
+        assert!(schema["definitions"]["Provider"].is_object());
+    }
 }
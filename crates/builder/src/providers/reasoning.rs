@@ -0,0 +1,243 @@
+use crate::providers::stream::{ChatDelta, ChatStream};
+use futures::stream::{self, StreamExt};
+
+// Reasoning models served without a reasoning parser (DeepSeek-R1 distills,
+// QwQ, Qwen3) think inside these tags before answering
+const OPEN_TAG: &str = "<think>";
+const CLOSE_TAG: &str = "</think>";
+
+// Where the splitter is in the answer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Only blanks so far, the answer may still open a block
+    #[default]
+    Start,
+    Thinking,
+    // After the block, or there was none: passed through untouched, so tags
+    // quoted in the answer (code, file contents) stay where they are
+    Answer,
+}
+
+// Splits a leading think block from the answer as the text arrives. Chunks
+// can end in the middle of a tag, so a possible tag start is held back
+// until the next push.
+#[derive(Debug, Default)]
+pub struct ThinkSplitter {
+    state: State,
+    // The answer usually starts with blank lines after the closing tag
+    trim_content: bool,
+    pending: String,
+}
+
+impl ThinkSplitter {
+    // For text whose block was opened by the chat template in the prompt
+    fn thinking() -> Self {
+        ThinkSplitter {
+            state: State::Thinking,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> ChatDelta {
+        let mut delta = ChatDelta::default();
+        let mut text = std::mem::take(&mut self.pending) + text;
+        loop {
+            match self.state {
+                State::Start => {
+                    let rest = text.trim_start();
+                    if let Some(rest) = rest.strip_prefix(OPEN_TAG) {
+                        text = rest.to_string();
+                        self.state = State::Thinking;
+                    } else if OPEN_TAG.starts_with(rest) {
+                        self.pending = text;
+                        return delta;
+                    } else {
+                        self.state = State::Answer;
+                    }
+                }
+                State::Thinking => {
+                    if let Some(i) = text.find(CLOSE_TAG) {
+                        delta.reasoning.push_str(&text[..i]);
+                        text.drain(..i + CLOSE_TAG.len());
+                        self.state = State::Answer;
+                        self.trim_content = true;
+                        continue;
+                    }
+                    let held = (1..CLOSE_TAG.len())
+                        .rev()
+                        .find(|&n| text.ends_with(&CLOSE_TAG[..n]))
+                        .unwrap_or(0);
+                    self.pending = text.split_off(text.len() - held);
+                    delta.reasoning.push_str(&text);
+                    return delta;
+                }
+                State::Answer => {
+                    let answer = if self.trim_content {
+                        text.trim_start()
+                    } else {
+                        &text
+                    };
+                    if !answer.is_empty() {
+                        self.trim_content = false;
+                        delta.content.push_str(answer);
+                    }
+                    return delta;
+                }
+            }
+        }
+    }
+
+    // Whatever was held back, once the text is complete
+    pub fn finish(&mut self) -> ChatDelta {
+        let mut delta = ChatDelta::default();
+        let pending = std::mem::take(&mut self.pending);
+        match self.state {
+            State::Thinking => delta.reasoning = pending,
+            State::Start | State::Answer => delta.content = pending,
+        }
+        delta
+    }
+}
+
+// Reasoning and answer of a complete text, None when it doesn't start with
+// a think block. Chat templates of reasoning models may open the block in
+// the prompt and leave only the closing tag, with `reasoning_model` all text
+// before it is reasoning then. A block cut off by max_tokens is reasoning up
+// to the end.
+pub fn split_think(text: &str, reasoning_model: bool) -> Option<(String, String)> {
+    let mut splitter = if text.trim_start().starts_with(OPEN_TAG) {
+        ThinkSplitter::default()
+    } else if reasoning_model && text.contains(CLOSE_TAG) {
+        ThinkSplitter::thinking()
+    } else {
+        return None;
+    };
+    let mut split = splitter.push(text);
+    let rest = splitter.finish();
+    split.reasoning.push_str(&rest.reasoning);
+    split.content.push_str(&rest.content);
+    Some((split.reasoning.trim().to_string(), split.content))
+}
+
+// Move a leading think block of a stream's content into `reasoning`. Only
+// an explicit opening tag is recognized, deltas can't be held back until a
+// closing tag shows up.
+pub fn split_think_stream(deltas: ChatStream) -> ChatStream {
+    let state = (deltas, ThinkSplitter::default(), false);
+    let split = stream::unfold(state, |(mut deltas, mut splitter, done)| async move {
+        if done {
+            return None;
+        }
+        let Some(delta) = deltas.next().await else {
+            let rest = splitter.finish();
+            return Some((Ok(rest), (deltas, splitter, true)));
+        };
+        let delta = delta.map(|delta| {
+            let mut split = splitter.push(&delta.content);
+            if delta.finish_reason.is_some() {
+                let rest = splitter.finish();
+                split.reasoning.push_str(&rest.reasoning);
+                split.content.push_str(&rest.content);
+            }
+            split.reasoning.insert_str(0, &delta.reasoning);
+            split.finish_reason = delta.finish_reason;
+            split
+        });
+        Some((delta, (deltas, splitter, false)))
+    });
+    Box::pin(split.filter(|delta| {
+        futures::future::ready(delta.as_ref().map_or(true, |delta| !delta.is_empty()))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::config::ProviderError;
+    use crate::providers::stream::collect_stream;
+
+    #[test]
+    fn test_split_think() {
+        let split = |text| split_think(text, false).unwrap();
+        assert_eq!(
+            split("\n<think>\nThe user greets me.\n</think>\n\nHello!"),
+            ("The user greets me.".to_string(), "Hello!".to_string())
+        );
+        // Cut off while thinking
+        assert_eq!(
+            split("<think>Let me see"),
+            ("Let me see".to_string(), String::new())
+        );
+        assert_eq!(
+            split("<think>\n\n</think>\n\nfn main() {}"),
+            (String::new(), "fn main() {}".to_string())
+        );
+        // Only a leading block counts, later tags are part of the answer
+        assert_eq!(
+            split("<think>Quote it</think>Use <think> and </think>"),
+            (
+                "Quote it".to_string(),
+                "Use <think> and </think>".to_string()
+            )
+        );
+        assert_eq!(split_think("  indented <b>text</b>", false), None);
+    }
+
+    #[test]
+    fn test_tags_inside_answer() {
+        let answer = r#"{"action_type":"write_file","content":"<think>a</think>b"}"#;
+        assert_eq!(split_think(answer, false), None);
+        let answer = r#"{"content":"const CLOSE_TAG: &str = \"</think>\";"}"#;
+        assert_eq!(split_think(answer, false), None);
+
+        // The opening tag was part of the prompt of a reasoning model
+        assert_eq!(
+            split_think("Plan first.</think>{\"ok\":true}", true),
+            Some(("Plan first.".to_string(), "{\"ok\":true}".to_string()))
+        );
+    }
+
+    fn split_chunks(chunks: &[&str]) -> ChatStream {
+        let mut deltas: Vec<Result<ChatDelta, ProviderError>> = chunks
+            .iter()
+            .map(|content| {
+                Ok(ChatDelta {
+                    content: content.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        deltas.push(Ok(ChatDelta {
+            finish_reason: Some("stop".to_string()),
+            ..Default::default()
+        }));
+        split_think_stream(Box::pin(stream::iter(deltas)))
+    }
+
+    #[tokio::test]
+    async fn test_split_think_stream() {
+        let chunks = [
+            "\n<thi",
+            "nk>Count",
+            " to 3</",
+            "think>\n",
+            "\n1, 2, 3 <",
+            "/b> <think>",
+        ];
+        let split: Vec<ChatDelta> = split_chunks(&chunks).map(Result::unwrap).collect().await;
+        assert!(split.iter().all(|delta| !delta.is_empty()));
+        assert_eq!(split.last().unwrap().finish_reason.as_deref(), Some("stop"));
+
+        let message = collect_stream(split_chunks(&chunks)).await.unwrap().message;
+        assert_eq!(message.content, "1, 2, 3 </b> <think>");
+        assert_eq!(message.reasoning.as_deref(), Some("Count to 3"));
+
+        // A normal answer that mentions the tags
+        let message = collect_stream(split_chunks(&["Use <", "think>", " and </think>"]))
+            .await
+            .unwrap()
+            .message;
+        assert_eq!(message.content, "Use <think> and </think>");
+        assert_eq!(message.reasoning, None);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::providers::config::ProviderError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+// How failed requests are retried. Can be set per provider in providers.toml:
+//
+// [providers.retry]
+// max_retries = 5
+// initial_backoff_ms = 1000
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    // Retries after the first attempt, 0 disables retrying
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    // Backoff growth factor between attempts
+    pub multiplier: f64,
+    // Random part of each delay, 0.2 means +/- 20%
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Policy that never retries
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    // Delay before retry number `attempt` (starting at 0). A `Retry-After`
+    // sent by the server wins over the computed backoff, up to max_backoff_ms.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(Duration::from_millis(self.max_backoff_ms));
+        }
+        let base = self.initial_backoff_ms as f64 * self.multiplier.powi(attempt as i32);
+        let capped = base.min(self.max_backoff_ms as f64);
+        let jitter = capped * self.jitter * (random_unit() * 2.0 - 1.0);
+        Duration::from_millis((capped + jitter).max(0.0) as u64)
+    }
+}
+
+// Value of a `Retry-After` header, either seconds or an HTTP date. A date
+// in the past means no wait.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// Random number in [0, 1), good enough for spreading retries
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(
+        SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Send the request built by `build`, retrying retryable failures according to
+// `policy`. Non-success responses are turned into ProviderError::Http.
+pub async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    build: F,
+) -> Result<reqwest::Response, ProviderError>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match build().send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => ProviderError::from_response(response).await,
+            Err(e) => ProviderError::transport("Request failed", e),
+        };
+        if attempt >= policy.max_retries || !error.is_retryable() {
+            return Err(error);
+        }
+        tokio::time::sleep(policy.delay(attempt, error.retry_after())).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff_ms: 1,
+            max_backoff_ms: 5,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_delay_grows_and_is_capped() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(0, None), Duration::from_millis(500));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(2000));
+        assert_eq!(policy.delay(20, None), Duration::from_millis(30_000));
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(3600))),
+            Duration::from_millis(30_000)
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let later = SystemTime::now() + Duration::from_secs(120);
+        let delay = parse_retry_after(&httpdate::fmt_http_date(later)).unwrap();
+        assert!(delay > Duration::from_secs(100) && delay <= Duration::from_secs(120));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_jitter_stays_in_bounds() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            let delay = policy.delay(1, None).as_millis();
+            assert!(
+                (800..=1200).contains(&delay),
+                "delay {} out of bounds",
+                delay
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_on_503_with_retry_after() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("POST", "/chat")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("model is loading")
+            .expect(1)
+            .create_async()
+            .await;
+        let ok = server
+            .mock("POST", "/chat")
+            .with_status(200)
+            .with_body("done")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let url = format!("{}/chat", server.url());
+        let response = send_with_retry(&fast_policy(), || client.post(&url))
+            .await
+            .unwrap();
+
+        unavailable.assert_async().await;
+        ok.assert_async().await;
+        assert_eq!(response.text().await.unwrap(), "done");
+    }
+
+    #[tokio::test]
+    async fn test_fatal_errors_are_not_retried() {
+        let mut server = mockito::Server::new_async().await;
+        let unauthorized = server
+            .mock("POST", "/chat")
+            .with_status(401)
+            .with_body(r#"{"error":{"type":"authentication_error","message":"invalid x-api-key"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let url = format!("{}/chat", server.url());
+        let error = send_with_retry(&fast_policy(), || client.post(&url))
+            .await
+            .unwrap_err();
+
+        unauthorized.assert_async().await;
+        assert_eq!(error.status(), Some(401));
+        assert!(error.to_string().contains("invalid x-api-key"));
+        match error {
+            ProviderError::Http { body, .. } => assert!(body.contains("authentication_error")),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("POST", "/chat")
+            .with_status(429)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let policy = RetryPolicy {
+            max_retries: 2,
+            ..fast_policy()
+        };
+        let client = reqwest::Client::new();
+        let url = format!("{}/chat", server.url());
+        let error = send_with_retry(&policy, || client.post(&url))
+            .await
+            .unwrap_err();
+
+        limited.assert_async().await;
+        assert_eq!(error.status(), Some(429));
+    }
+}
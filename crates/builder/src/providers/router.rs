@@ -0,0 +1,409 @@
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::CompletionResult;
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::stream::ChatStream;
+use crate::providers::structured::validate;
+use crate::providers::tool_call::ToolDefinition;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// A named list of provider/model pairs, declared in providers.toml:
+//
+// [[routes]]
+// name = "coder"
+// strategy = "fallback"
+// timeout_secs = 30
+//
+// [[routes.targets]]
+// provider = "LmStudio"
+// model = "qwen2.5-7b-instruct-1m"
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Route {
+    pub name: String,
+    #[serde(default)]
+    pub strategy: RouteStrategy,
+    // Time limit for a single target, the next one is tried when it runs out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    pub targets: Vec<RouteTarget>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct RouteTarget {
+    // Name of the provider in providers.toml
+    pub provider: String,
+    pub model: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteStrategy {
+    // Always start with the first target, move down the list on failure
+    #[default]
+    Fallback,
+    // Start with the next target on every call to spread the load, still
+    // falling back to the others on failure
+    RoundRobin,
+}
+
+impl Route {
+    pub fn new(name: impl Into<String>, strategy: RouteStrategy) -> Self {
+        Route {
+            name: name.into(),
+            strategy,
+            timeout_secs: None,
+            targets: Vec::new(),
+        }
+    }
+
+    pub fn with_target(mut self, provider: impl Into<String>, model: impl Into<String>) -> Self {
+        self.targets.push(RouteTarget {
+            provider: provider.into(),
+            model: model.into(),
+        });
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout_secs: u64) -> Self {
+        self.timeout_secs = Some(timeout_secs);
+        self
+    }
+}
+
+// ChatProvider that sends each call to the targets of a route until one of
+// them answers. Built by `Providers::route`.
+#[derive(Debug)]
+pub struct RoutedProvider {
+    name: String,
+    strategy: RouteStrategy,
+    timeout: Option<Duration>,
+    targets: Vec<Box<dyn ChatProvider>>,
+    next: AtomicUsize,
+    // Index of the target that gave the last answer
+    answered: AtomicUsize,
+}
+
+impl RoutedProvider {
+    pub fn new(route: &Route, targets: Vec<Box<dyn ChatProvider>>) -> Result<Self, ProviderError> {
+        if targets.is_empty() {
+            return Err(ProviderError::Configuration(format!(
+                "Route {} has no targets",
+                route.name
+            )));
+        }
+        Ok(RoutedProvider {
+            name: route.name.clone(),
+            strategy: route.strategy,
+            timeout: route.timeout_secs.map(Duration::from_secs),
+            targets,
+            next: AtomicUsize::new(0),
+            answered: AtomicUsize::new(0),
+        })
+    }
+
+    // Indices of the targets in the order they should be tried for this call
+    fn ordered_targets(&self) -> impl Iterator<Item = usize> {
+        let start = match self.strategy {
+            RouteStrategy::Fallback => 0,
+            RouteStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+        let len = self.targets.len();
+        (0..len).map(move |i| (start + i) % len)
+    }
+
+    // Run `call` against the targets in order, returning the first success.
+    // Errors and timeouts move on to the next target.
+    async fn try_targets<'a, T, F, Fut>(&'a self, call: F) -> Result<T, ProviderError>
+    where
+        F: Fn(&'a dyn ChatProvider) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let mut failures = Vec::new();
+        for i in self.ordered_targets() {
+            let target = self.targets[i].as_ref();
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, call(target))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(ProviderError::ApiCall(format!(
+                            "Timed out after {}s",
+                            timeout.as_secs()
+                        )))
+                    }),
+                None => call(target).await,
+            };
+            match result {
+                Ok(value) => {
+                    self.answered.store(i, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                // The caller gave up, the other targets must not be tried
+                Err(ProviderError::Cancelled) => return Err(ProviderError::Cancelled),
+                Err(e) => failures.push(format!("{}: {}", target.model(), e)),
+            }
+        }
+        Err(ProviderError::ApiCall(format!(
+            "All targets of route {} failed: {}",
+            self.name,
+            failures.join("; ")
+        )))
+    }
+}
+
+#[async_trait]
+impl ChatProvider for RoutedProvider {
+    // Model of the target that gave the last answer, the first target
+    // before any call. With concurrent calls it may belong to another call.
+    fn model(&self) -> &str {
+        self.targets[self.answered.load(Ordering::Relaxed)].model()
+    }
+
+    // Every target uses its own model, `model_override` is ignored. With a
+    // response schema, an answer that doesn't match it counts as a failure
+    // so the next target gets a chance.
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let validator = response_schema
+            .as_ref()
+            .map(jsonschema::validator_for)
+            .transpose()
+            .map_err(|e| {
+                ProviderError::RequestPreparation(format!("Invalid response schema: {}", e))
+            })?;
+        self.try_targets(|target| {
+            let messages = messages.clone();
+            let response_schema = response_schema.clone();
+            let validator = validator.as_ref();
+            async move {
+                let result = target
+                    .complete(
+                        messages,
+                        None,
+                        temperature_override,
+                        max_tokens_override,
+                        response_schema,
+                    )
+                    .await?;
+                if let Some(validator) = validator {
+                    validate(validator, result.content().unwrap_or_default()).map_err(
+                        |errors| {
+                            ProviderError::ResponseParsing(format!(
+                                "Structured output does not match the schema: {}",
+                                errors.join(", ")
+                            ))
+                        },
+                    )?;
+                }
+                Ok(result)
+            }
+        })
+        .await
+    }
+
+    // Falls back only while opening the stream, errors in the middle of a
+    // stream are returned to the caller. `model_override` is ignored.
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        self.try_targets(|target| {
+            target.chat_completion_stream(
+                messages.clone(),
+                None,
+                temperature_override,
+                max_tokens_override,
+                response_schema.clone(),
+            )
+        })
+        .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        self.try_targets(|target| target.chat_with_tools(messages.clone(), tools.clone()))
+            .await
+    }
+
+    // Models of every target that could be reached
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let mut models = Vec::new();
+        for target in &self.targets {
+            for model in target.list_models().await.unwrap_or_default() {
+                if !models.contains(&model) {
+                    models.push(model);
+                }
+            }
+        }
+        Ok(models)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::tests::mock_provider;
+    use serde_json::json;
+
+    fn target(server: &mockito::Server, model: &str) -> Box<dyn ChatProvider> {
+        Box::new(mock_provider(server, model))
+    }
+
+    fn answer(content: &str) -> String {
+        json!({"choices": [{"message": {"role": "assistant", "content": content}}]}).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_fallback_on_error_and_bad_json() {
+        let mut down = mockito::Server::new_async().await;
+        down.mock("POST", "/v1/chat/completions")
+            .with_status(500)
+            .create_async()
+            .await;
+        let mut chatty = mockito::Server::new_async().await;
+        chatty
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer("Sure! Here is the JSON"))
+            .create_async()
+            .await;
+        let mut wrong = mockito::Server::new_async().await;
+        wrong
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer(r#"{"done":true}"#))
+            .create_async()
+            .await;
+        let mut good = mockito::Server::new_async().await;
+        good.mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer(r#"{"ok":true}"#))
+            .create_async()
+            .await;
+
+        let route = Route::new("coder", RouteStrategy::Fallback);
+        let provider = RoutedProvider::new(
+            &route,
+            vec![
+                target(&down, "down"),
+                target(&chatty, "chatty"),
+                target(&wrong, "wrong"),
+                target(&good, "good"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(provider.model(), "down");
+        let schema = json!({"type": "object", "required": ["ok"]});
+        let value = provider
+            .structured_completion(vec![ChatMessage::user("hi")], schema)
+            .await
+            .unwrap();
+        assert_eq!(value, json!({"ok": true}));
+        assert_eq!(provider.model(), "good");
+
+        // Without a schema the chatty target is good enough
+        let text = provider
+            .chat_completion(vec![ChatMessage::user("hi")], None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(text, "Sure! Here is the JSON");
+        assert_eq!(provider.model(), "chatty");
+    }
+
+    #[tokio::test]
+    async fn test_all_targets_failing() {
+        let mut down = mockito::Server::new_async().await;
+        down.mock("POST", "/v1/chat/completions")
+            .with_status(401)
+            .with_body(r#"{"error":{"message":"bad key"}}"#)
+            .create_async()
+            .await;
+
+        let route = Route::new("coder", RouteStrategy::Fallback);
+        let provider = RoutedProvider::new(&route, vec![target(&down, "phi-4")]).unwrap();
+        let error = provider
+            .chat_completion(vec![ChatMessage::user("hi")], None, None, None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "API call error: All targets of route coder failed: phi-4: API returned status code 401: bad key"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let mut first = mockito::Server::new_async().await;
+        let first_mock = first
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer("first"))
+            .expect(2)
+            .create_async()
+            .await;
+        let mut second = mockito::Server::new_async().await;
+        let second_mock = second
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer("second"))
+            .expect(1)
+            .create_async()
+            .await;
+
+        let route = Route::new("coder", RouteStrategy::RoundRobin);
+        let provider =
+            RoutedProvider::new(&route, vec![target(&first, "a"), target(&second, "b")]).unwrap();
+        let mut answers = Vec::new();
+        for _ in 0..3 {
+            answers.push(
+                provider
+                    .chat_completion(vec![ChatMessage::user("hi")], None, None, None, None)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        first_mock.assert_async().await;
+        second_mock.assert_async().await;
+        assert_eq!(answers, vec!["first", "second", "first"]);
+    }
+
+    #[test]
+    fn test_route_from_toml() {
+        let route: Route = toml::from_str(
+            r#"
+            name = "coder"
+            strategy = "round_robin"
+            timeout_secs = 30
+
+            [[targets]]
+            provider = "LmStudio"
+            model = "qwen2.5-7b-instruct-1m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            route,
+            Route::new("coder", RouteStrategy::RoundRobin)
+                .with_timeout(30)
+                .with_target("LmStudio", "qwen2.5-7b-instruct-1m")
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use crate::providers::chat::ChatProvider;
+use crate::providers::completion::CompletionResult;
+use crate::providers::config::ProviderError;
+use crate::providers::content::MessageContent;
+use crate::providers::openai::ChatMessage;
+use crate::providers::stream::ChatStream;
+use crate::providers::tool_call::ToolDefinition;
+use async_trait::async_trait;
+use serde_json::{Value, json};
+
+// Nesting depth up to which `example` follows the schema
+const MAX_EXAMPLE_DEPTH: usize = 8;
+
+// Instructions for servers that reject `response_format: json_schema`: the
+// schema and an example answer rendered into the system prompt
+pub fn schema_prompt(schema: &Value) -> String {
+    let pretty = |value: &Value| serde_json::to_string_pretty(value).unwrap_or_default();
+    format!(
+        "Answer with a single JSON value matching this JSON schema and nothing else.\n\n\
+         Schema:\n```json\n{}\n```\n\nExample answer:\n```json\n{}\n```",
+        pretty(schema),
+        pretty(&example(schema, schema, 0))
+    )
+}
+
+// Placeholder value for `schema`, `root` is used to resolve `$ref`s
+fn example(schema: &Value, root: &Value, depth: usize) -> Value {
+    if depth > MAX_EXAMPLE_DEPTH {
+        return Value::Null;
+    }
+    let next = |schema: &Value| example(schema, root, depth + 1);
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        return reference
+            .strip_prefix("#/")
+            .map(|path| format!("/{}", path))
+            .and_then(|pointer| root.pointer(&pointer))
+            .map(next)
+            .unwrap_or(Value::Null);
+    }
+    for keyword in ["const", "default"] {
+        if let Some(value) = schema.get(keyword) {
+            return value.clone();
+        }
+    }
+    if let Some(first) = schema.get("enum").and_then(|e| e.get(0)) {
+        return first.clone();
+    }
+    for keyword in ["oneOf", "anyOf", "allOf"] {
+        if let Some(first) = schema.get(keyword).and_then(|s| s.get(0)) {
+            return next(first);
+        }
+    }
+    // `["string", "null"]` is how schemars writes an Option
+    let kind = match schema.get("type") {
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|t| *t != "null"),
+        Some(kind) => kind.as_str(),
+        None if schema.get("properties").is_some() => Some("object"),
+        None => None,
+    };
+    match kind {
+        Some("object") => Value::Object(
+            schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .map(|(name, property)| (name.clone(), next(property)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+        Some("array") => json!([schema.get("items").map(next).unwrap_or(Value::Null)]),
+        Some("string") => json!("string"),
+        Some("integer") => json!(0),
+        Some("number") => json!(0.0),
+        Some("boolean") => json!(true),
+        _ => Value::Null,
+    }
+}
+
+// Pull the JSON part out of a model answer: the first fenced block holding
+// JSON, otherwise the text from the first `{` or `[` to its matching bracket.
+// Truncated answers return everything after the opening bracket.
+pub fn extract_json(text: &str) -> Option<&str> {
+    let mut rest = text;
+    while let Some(start) = rest.find("```") {
+        let block = &rest[start + 3..];
+        // Skip the language tag
+        let block = block.split_once('\n').map_or("", |(_, body)| body);
+        let (body, after) = block.split_once("```").unwrap_or((block, ""));
+        if body.trim_start().starts_with(['{', '[']) {
+            return Some(body.trim());
+        }
+        rest = after;
+    }
+    let start = text.find(['{', '['])?;
+    let candidate = &text[start..];
+    Some(match matching_end(candidate) {
+        Some(end) => &candidate[..end],
+        None => candidate.trim_end(),
+    })
+}
+
+// Byte offset just past the bracket closing the one `text` starts with
+fn matching_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '{' | '[' => depth += 1,
+                '}' | ']' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return Some(i + 1);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    None
+}
+
+// Best effort fix for the mistakes small models make in JSON: single quoted
+// strings, raw newlines in strings, trailing commas and answers cut off
+// before the closing quotes and brackets
+pub fn repair_json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut closers = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match quote {
+            Some(q) if escaped => {
+                escaped = false;
+                // `\'` is only needed inside single quoted strings
+                if !(q == '\'' && c == '\'') {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => {
+                quote = None;
+                out.push('"');
+            }
+            Some(_) if c == '"' => out.push_str("\\\""),
+            Some(_) if c == '\n' => out.push_str("\\n"),
+            Some(_) => out.push(c),
+            None => match c {
+                '"' | '\'' => {
+                    quote = Some(c);
+                    out.push('"');
+                }
+                '{' => {
+                    closers.push('}');
+                    out.push(c);
+                }
+                '[' => {
+                    closers.push(']');
+                    out.push(c);
+                }
+                '}' | ']' => {
+                    trim_trailing_comma(&mut out);
+                    if closers.last() == Some(&c) {
+                        closers.pop();
+                    }
+                    out.push(c);
+                }
+                _ => out.push(c),
+            },
+        }
+    }
+    if quote.is_some() {
+        out.push('"');
+    }
+    trim_trailing_comma(&mut out);
+    if out.ends_with(':') {
+        out.push_str("null");
+    }
+    while let Some(closer) = closers.pop() {
+        trim_trailing_comma(&mut out);
+        out.push(closer);
+    }
+    out
+}
+
+fn trim_trailing_comma(out: &mut String) {
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    if out.ends_with(',') {
+        out.pop();
+    }
+}
+
+// The JSON value in a model answer, repaired when needed. None when no JSON
+// could be found.
+pub fn parse_lenient(text: &str) -> Option<Value> {
+    let json = extract_json(text)?;
+    serde_json::from_str(json)
+        .or_else(|_| serde_json::from_str(&repair_json(json)))
+        .ok()
+}
+
+// Add `prompt` to the system message, creating one if needed
+fn with_system_prompt(mut messages: Vec<ChatMessage>, prompt: &str) -> Vec<ChatMessage> {
+    match messages.first_mut() {
+        Some(first) if first.role == "system" => {
+            let text = std::mem::take(&mut first.content).into_text();
+            first.content = MessageContent::Text(format!("{}\n\n{}", text, prompt));
+        }
+        _ => messages.insert(0, ChatMessage::system(prompt)),
+    }
+    messages
+}
+
+// ChatProvider decorator for models whose server rejects
+// `response_format: json_schema`. The schema goes into the system prompt
+// instead and JSON answers are extracted and repaired before they are
+// returned. Streams only get the prompt, their deltas are passed through.
+#[derive(Debug)]
+pub struct SchemaPromptProvider {
+    inner: Box<dyn ChatProvider>,
+}
+
+impl SchemaPromptProvider {
+    pub fn new(inner: Box<dyn ChatProvider>) -> Self {
+        SchemaPromptProvider { inner }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for SchemaPromptProvider {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    async fn complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<CompletionResult, ProviderError> {
+        let Some(schema) = response_schema else {
+            return self
+                .inner
+                .complete(
+                    messages,
+                    model_override,
+                    temperature_override,
+                    max_tokens_override,
+                    None,
+                )
+                .await;
+        };
+        let messages = with_system_prompt(messages, &schema_prompt(&schema));
+        let mut result = self
+            .inner
+            .complete(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                None,
+            )
+            .await?;
+        // Unusable answers are left as they are for the caller to report
+        for choice in &mut result.choices {
+            if let Some(value) = parse_lenient(&choice.message.content.to_text()) {
+                choice.message.content = MessageContent::Text(value.to_string());
+            }
+        }
+        Ok(result)
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model_override: Option<String>,
+        temperature_override: Option<f32>,
+        max_tokens_override: Option<u32>,
+        response_schema: Option<serde_json::Value>,
+    ) -> Result<ChatStream, ProviderError> {
+        let messages = match &response_schema {
+            Some(schema) => with_system_prompt(messages, &schema_prompt(schema)),
+            None => messages,
+        };
+        self.inner
+            .chat_completion_stream(
+                messages,
+                model_override,
+                temperature_override,
+                max_tokens_override,
+                None,
+            )
+            .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: Vec<ChatMessage>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChatMessage, ProviderError> {
+        self.inner.chat_with_tools(messages, tools).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.list_models().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::tests::mock_provider;
+    use mockito::Matcher;
+
+    #[test]
+    fn test_extract_json() {
+        let fenced = "Sure, here it is:\n```json\n{\"a\": 1}\n```\nAnything else?";
+        assert_eq!(extract_json(fenced), Some("{\"a\": 1}"));
+        let prose = "The answer is {\"a\": {\"b\": \"}\"}} as requested.";
+        assert_eq!(extract_json(prose), Some("{\"a\": {\"b\": \"}\"}}"));
+        assert_eq!(extract_json("cut off: [1, 2"), Some("[1, 2"));
+        assert_eq!(extract_json("no json here"), None);
+    }
+
+    #[test]
+    fn test_repair_json() {
+        let cases = [
+            (
+                "{'a': 'it\\'s', 'b': [1, 2,],}",
+                json!({"a": "it's", "b": [1, 2]}),
+            ),
+            (
+                "{\"a\": \"line\nbreak\", \"b\": {\"c\": [1, 2",
+                json!({"a": "line\nbreak", "b": {"c": [1, 2]}}),
+            ),
+            ("{\"a\": \"trunc", json!({"a": "trunc"})),
+            ("{\"a\": 1, \"b\":", json!({"a": 1, "b": null})),
+            ("{'say': 'a \"quote\"'}", json!({"say": "a \"quote\""})),
+        ];
+        for (input, expected) in cases {
+            let repaired = repair_json(input);
+            assert_eq!(
+                serde_json::from_str::<Value>(&repaired)
+                    .unwrap_or_else(|e| panic!("{}: {}", repaired, e)),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_schema_prompt_example() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Answer {
+            reasoning: Vec<String>,
+            summary: Option<String>,
+            kind: Kind,
+        }
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        enum Kind {
+            Code,
+            Text,
+        }
+        let schema = serde_json::to_value(schemars::schema_for!(Answer)).unwrap();
+        assert_eq!(
+            example(&schema, &schema, 0),
+            json!({"reasoning": ["string"], "summary": "string", "kind": "Code"})
+        );
+        assert!(schema_prompt(&schema).contains("\"reasoning\""));
+    }
+
+    #[tokio::test]
+    async fn test_schema_goes_into_the_prompt() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex("Example answer".to_string()),
+                Matcher::Regex(r#""role":"system""#.to_string()),
+            ]))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {
+                    "role": "assistant",
+                    "content": "Here you go:\n```json\n{'sum': 3,}\n```"
+                }}]})
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let provider = SchemaPromptProvider::new(Box::new(mock_provider(&server, "llamafile")));
+
+        let value = provider
+            .structured_completion(
+                vec![ChatMessage::user("1 + 2")],
+                json!({"type": "object", "properties": {"sum": {"type": "integer"}}}),
+            )
+            .await
+            .unwrap();
+        mock.assert_async().await;
+        assert_eq!(value, json!({"sum": 3}));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+
+// A single piece of a streamed completion
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChatDelta {
+    pub content: String,
+    // Thinking of reasoning models, see providers::reasoning
+    pub reasoning: String,
+    pub finish_reason: Option<String>,
+}
+
+impl ChatDelta {
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty() && self.reasoning.is_empty() && self.finish_reason.is_none()
+    }
+}
+
+// Stream of content deltas returned by the streaming chat APIs
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatDelta, ProviderError>> + Send>>;
+
+// The final message put back together from a stream of deltas
+#[derive(Debug, Clone)]
+pub struct StreamedMessage {
+    pub message: ChatMessage,
+    pub finish_reason: Option<String>,
+}
+
+// Drain a ChatStream, concatenating the deltas into the final assistant message
+pub async fn collect_stream(mut stream: ChatStream) -> Result<StreamedMessage, ProviderError> {
+    let mut content = String::new();
+    let mut reasoning = String::new();
+    let mut finish_reason = None;
+    while let Some(delta) = stream.next().await {
+        let delta = delta?;
+        content.push_str(&delta.content);
+        reasoning.push_str(&delta.reasoning);
+        if delta.finish_reason.is_some() {
+            finish_reason = delta.finish_reason;
+        }
+    }
+    let mut message = ChatMessage::assistant(content);
+    message.reasoning = (!reasoning.is_empty()).then_some(reasoning);
+    Ok(StreamedMessage {
+        message,
+        finish_reason,
+    })
+}
+
+// One event of a `text/event-stream` response
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+// Incremental decoder turning network chunks into complete items
+pub trait ChunkDecoder {
+    type Item;
+    fn push(&mut self, chunk: &[u8]) -> Vec<Self::Item>;
+    // Flush whatever is left once the body is complete
+    fn finish(&mut self) -> Option<Self::Item>;
+}
+
+// Incremental decoder for server sent events. Network chunks can split
+// events (and UTF-8 characters) anywhere, so bytes are buffered until a
+// blank line terminates the event.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    // Bytes already searched for a separator, so a long event arriving in
+    // many chunks isn't rescanned from the start on every push
+    scanned: usize,
+}
+
+impl ChunkDecoder for SseDecoder {
+    type Item = SseEvent;
+
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some((end, separator_len)) = find_event_end(&self.buffer[self.scanned..]) {
+            let end = self.scanned + end;
+            let raw: Vec<u8> = self.buffer.drain(..end + separator_len).collect();
+            self.scanned = 0;
+            if let Some(event) = parse_event(&String::from_utf8_lossy(&raw[..end])) {
+                events.push(event);
+            }
+        }
+        // The next chunk may complete a separator started at the end
+        self.scanned = self.buffer.len().saturating_sub(3);
+        events
+    }
+
+    fn finish(&mut self) -> Option<SseEvent> {
+        let raw = std::mem::take(&mut self.buffer);
+        self.scanned = 0;
+        parse_event(&String::from_utf8_lossy(&raw))
+    }
+}
+
+// Decoder for newline delimited JSON, yields one non-empty line per item
+#[derive(Debug, Default)]
+pub struct LineDecoder {
+    buffer: Vec<u8>,
+}
+
+impl ChunkDecoder for LineDecoder {
+    type Item = String;
+
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let raw: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&raw).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    fn finish(&mut self) -> Option<String> {
+        let raw = std::mem::take(&mut self.buffer);
+        let line = String::from_utf8_lossy(&raw).trim().to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+fn find_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    (0..buffer.len()).find_map(|i| {
+        if buffer[i..].starts_with(b"\r\n\r\n") {
+            Some((i, 4))
+        } else if buffer[i..].starts_with(b"\n\n") {
+            Some((i, 2))
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_event(raw: &str) -> Option<SseEvent> {
+    let mut event = SseEvent::default();
+    let mut data: Vec<&str> = Vec::new();
+    for line in raw.lines() {
+        // Lines starting with a colon are comments / keep-alives
+        if line.is_empty() || line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event.event = Some(value.to_string()),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+    if data.is_empty() && event.event.is_none() {
+        return None;
+    }
+    event.data = data.join("\n");
+    Some(event)
+}
+
+// Turn a streaming HTTP response into a stream of decoded events
+pub fn sse_events(
+    response: reqwest::Response,
+) -> impl Stream<Item = Result<SseEvent, ProviderError>> + Send {
+    decode_response(response, SseDecoder::default())
+}
+
+// Turn a streaming NDJSON response into a stream of lines
+pub fn ndjson_lines(
+    response: reqwest::Response,
+) -> impl Stream<Item = Result<String, ProviderError>> + Send {
+    decode_response(response, LineDecoder::default())
+}
+
+fn decode_response<D>(
+    response: reqwest::Response,
+    decoder: D,
+) -> impl Stream<Item = Result<D::Item, ProviderError>> + Send
+where
+    D: ChunkDecoder + Send + 'static,
+    D::Item: Send + 'static,
+{
+    let state = (
+        response.bytes_stream().boxed(),
+        decoder,
+        VecDeque::new(),
+        false,
+    );
+    stream::unfold(
+        state,
+        |(mut bytes, mut decoder, mut pending, mut done)| async move {
+            loop {
+                if let Some(item) = pending.pop_front() {
+                    return Some((Ok(item), (bytes, decoder, pending, done)));
+                }
+                if done {
+                    return None;
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => pending.extend(decoder.push(&chunk)),
+                    Some(Err(e)) => {
+                        let err = ProviderError::transport("Stream interrupted", e);
+                        return Some((Err(err), (bytes, decoder, pending, true)));
+                    }
+                    None => {
+                        done = true;
+                        pending.extend(decoder.finish());
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_handles_split_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: {\"a\"").is_empty());
+        let events = decoder.push(b":1}\n\n: keep-alive\n\nevent: done\r\ndata: [DONE]\r\n\r\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: None,
+                    data: "{\"a\":1}".to_string()
+                },
+                SseEvent {
+                    event: Some("done".to_string()),
+                    data: "[DONE]".to_string()
+                },
+            ]
+        );
+        assert!(decoder.finish().is_none());
+    }
+
+    #[test]
+    fn test_decoder_handles_split_separator() {
+        let mut decoder = SseDecoder::default();
+        for chunk in ["data: a", "bc\r", "\n\r"] {
+            assert!(decoder.push(chunk.as_bytes()).is_empty());
+        }
+        assert_eq!(decoder.push(b"\ndata: d\n")[0].data, "abc");
+        assert_eq!(decoder.push(b"\n")[0].data, "d");
+    }
+
+    #[test]
+    fn test_decoder_flushes_unterminated_event() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push("data: zażółć".as_bytes()).is_empty());
+        assert_eq!(decoder.finish().unwrap().data, "zażółć");
+    }
+
+    #[test]
+    fn test_line_decoder() {
+        let mut decoder = LineDecoder::default();
+        assert!(decoder.push(b"{\"a\"").is_empty());
+        assert_eq!(
+            decoder.push(b":1}\n\n{\"b\":2}\r\n{"),
+            vec!["{\"a\":1}", "{\"b\":2}"]
+        );
+        assert_eq!(decoder.finish().as_deref(), Some("{"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_stream() {
+        let deltas = vec![
+            Ok(ChatDelta {
+                reasoning: "Greet".to_string(),
+                ..Default::default()
+            }),
+            Ok(ChatDelta {
+                content: "Hel".to_string(),
+                ..Default::default()
+            }),
+            Ok(ChatDelta {
+                content: "lo".to_string(),
+                reasoning: String::new(),
+                finish_reason: Some("stop".to_string()),
+            }),
+        ];
+        let collected = collect_stream(Box::pin(stream::iter(deltas)))
+            .await
+            .unwrap();
+        assert_eq!(collected.message.content, "Hello");
+        assert_eq!(collected.message.role, "assistant");
+        assert_eq!(collected.message.reasoning.as_deref(), Some("Greet"));
+        assert_eq!(collected.finish_reason.as_deref(), Some("stop"));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::providers::chat::ChatProvider;
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use schemars::{JsonSchema, schema_for};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+// One answer of the model that could not be turned into the requested type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructuredAttempt {
+    pub response: String,
+    // Schema validation or parse errors, `<json pointer>: <message>`
+    pub errors: Vec<String>,
+}
+
+// Typed structured output for every ChatProvider, including `dyn ChatProvider`
+pub trait StructuredChat {
+    // Ask for an answer matching `schema_for!(T)`. Answers that are not valid
+    // JSON or break the schema are sent back with the exact errors, at most
+    // `max_repairs` times, before giving up with ProviderError::Structured.
+    fn chat_structured<T>(
+        &self,
+        messages: Vec<ChatMessage>,
+        max_repairs: usize,
+    ) -> impl Future<Output = Result<T, ProviderError>> + Send
+    where
+        T: JsonSchema + DeserializeOwned + Send;
+}
+
+impl<P: ChatProvider + ?Sized> StructuredChat for P {
+    async fn chat_structured<T>(
+        &self,
+        mut messages: Vec<ChatMessage>,
+        max_repairs: usize,
+    ) -> Result<T, ProviderError>
+    where
+        T: JsonSchema + DeserializeOwned + Send,
+    {
+        let schema = serde_json::to_value(schema_for!(T)).map_err(|e| {
+            ProviderError::RequestPreparation(format!("Cannot serialize schema: {}", e))
+        })?;
+        let validator = jsonschema::validator_for(&schema).map_err(|e| {
+            ProviderError::RequestPreparation(format!("Invalid response schema: {}", e))
+        })?;
+        let mut attempts = Vec::new();
+        for _ in 0..=max_repairs {
+            let response = self
+                .chat_completion(messages.clone(), None, None, None, Some(schema.clone()))
+                .await?;
+            let errors = match validate(&validator, &response) {
+                // The schema can be looser than serde, e.g. for untagged enums
+                Ok(value) => match serde_json::from_value::<T>(value) {
+                    Ok(parsed) => return Ok(parsed),
+                    Err(e) => vec![format!("/: {}", e)],
+                },
+                Err(errors) => errors,
+            };
+            messages.push(ChatMessage::assistant(response.clone()));
+            messages.push(ChatMessage::user(repair_prompt(&errors)));
+            attempts.push(StructuredAttempt { response, errors });
+        }
+        Err(ProviderError::Structured { attempts })
+    }
+}
+
+// Parse `response` and check it against the schema of `validator`, returning
+// every problem as `<json pointer>: <message>`
+pub fn validate(
+    validator: &jsonschema::Validator,
+    response: &str,
+) -> Result<serde_json::Value, Vec<String>> {
+    let value: serde_json::Value =
+        serde_json::from_str(response).map_err(|e| vec![format!("/: not valid JSON: {}", e)])?;
+    let errors: Vec<String> = validator
+        .iter_errors(&value)
+        .map(|e| format!("{}: {}", pointer(&e.instance_path.to_string()), e))
+        .collect();
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors)
+    }
+}
+
+// The root of the document has an empty pointer
+fn pointer(path: &str) -> &str {
+    if path.is_empty() { "/" } else { path }
+}
+
+fn repair_prompt(errors: &[String]) -> String {
+    format!(
+        "Your answer does not match the JSON schema:\n- {}\nReply with the corrected JSON only.",
+        errors.join("\n- ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::tests::mock_provider;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Sum {
+        a: i64,
+        b: i64,
+    }
+
+    fn answer(content: &str) -> String {
+        json!({"choices": [{"message": {"role": "assistant", "content": content}}]}).to_string()
+    }
+
+    fn provider(server: &mockito::Server) -> Box<dyn ChatProvider> {
+        Box::new(mock_provider(server, "phi-4"))
+    }
+
+    #[tokio::test]
+    async fn test_repairs_invalid_answer() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(
+                json!({"messages": [{"role": "user"}]}),
+            ))
+            .with_status(200)
+            .with_body(answer(r#"{"a": 1, "b": "two"}"#))
+            .expect(1)
+            .create_async()
+            .await;
+        let repaired = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::Regex(
+                r#"/b: \\"two\\" is not of type"#.to_string(),
+            ))
+            .with_status(200)
+            .with_body(answer(r#"{"a": 1, "b": 2}"#))
+            .create_async()
+            .await;
+
+        let sum: Sum = provider(&server)
+            .chat_structured(vec![ChatMessage::user("1 and 2")], 2)
+            .await
+            .unwrap();
+        assert_eq!(sum, Sum { a: 1, b: 2 });
+        first.assert_async().await;
+        repaired.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_reports_every_attempt() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/v1/chat/completions")
+            .with_status(200)
+            .with_body(answer("Sure! 1 + 2 = 3"))
+            .expect(2)
+            .create_async()
+            .await;
+
+        let error = provider(&server)
+            .chat_structured::<Sum>(vec![ChatMessage::user("1 and 2")], 1)
+            .await
+            .unwrap_err();
+        let ProviderError::Structured { attempts } = error else {
+            panic!("expected a structured output error");
+        };
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].response, "Sure! 1 + 2 = 3");
+        assert!(attempts[1].errors[0].starts_with("/: not valid JSON"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::providers::config::ProviderError;
+use std::path::Path;
+
+// Counts the tokens of a text, used by Conversation to stay inside the
+// context window
+pub trait Tokenizer: Send + Sync + std::fmt::Debug {
+    fn count(&self, text: &str) -> usize;
+}
+
+// Guess based on the text length, good enough when the real tokenizer is
+// not at hand. English text and code average about 4 characters per token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CharEstimator {
+    pub chars_per_token: f32,
+}
+
+impl Default for CharEstimator {
+    fn default() -> Self {
+        CharEstimator {
+            chars_per_token: 4.0,
+        }
+    }
+}
+
+impl Tokenizer for CharEstimator {
+    fn count(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+// Exact counts from a Hugging Face `tokenizer.json`, as shipped next to
+// most open weights models
+#[cfg(feature = "tokenizers")]
+#[derive(Debug)]
+pub struct HfTokenizer(tokenizers::Tokenizer);
+
+#[cfg(feature = "tokenizers")]
+impl HfTokenizer {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProviderError> {
+        let path = path.as_ref();
+        tokenizers::Tokenizer::from_file(path)
+            .map(HfTokenizer)
+            .map_err(|e| {
+                ProviderError::Configuration(format!(
+                    "Cannot load tokenizer {}: {}",
+                    path.display(),
+                    e
+                ))
+            })
+    }
+}
+
+#[cfg(feature = "tokenizers")]
+impl Tokenizer for HfTokenizer {
+    fn count(&self, text: &str) -> usize {
+        match self.0.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            Err(_) => CharEstimator::default().count(text),
+        }
+    }
+}
+
+// HfTokenizer for `path`, or an error telling how to enable it
+#[cfg(not(feature = "tokenizers"))]
+pub fn from_file(path: impl AsRef<Path>) -> Result<Box<dyn Tokenizer>, ProviderError> {
+    Err(ProviderError::Configuration(format!(
+        "Cannot load tokenizer {}: cb-builder was built without the `tokenizers` feature",
+        path.as_ref().display()
+    )))
+}
+
+#[cfg(feature = "tokenizers")]
+pub fn from_file(path: impl AsRef<Path>) -> Result<Box<dyn Tokenizer>, ProviderError> {
+    Ok(Box::new(HfTokenizer::from_file(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_estimator() {
+        let estimator = CharEstimator::default();
+        assert_eq!(estimator.count(""), 0);
+        assert_eq!(estimator.count("fn main() {}"), 3);
+        assert_eq!(estimator.count("żółw"), 1);
+    }
+
+    #[cfg(feature = "tokenizers")]
+    #[test]
+    fn test_hf_tokenizer() {
+        let tmp_dir = tempdir::TempDir::new("tokenizer-test").unwrap();
+        let path = tmp_dir.path().join("tokenizer.json");
+        let tokenizer = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"hello": 0, "world": 1, "[UNK]": 2},
+                "unk_token": "[UNK]"
+            }
+        });
+        std::fs::write(&path, tokenizer.to_string()).unwrap();
+        let tokenizer = from_file(&path).unwrap();
+        assert_eq!(tokenizer.count("hello big world"), 3);
+        assert!(from_file(tmp_dir.path().join("missing.json")).is_err());
+    }
+}
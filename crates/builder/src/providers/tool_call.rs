@@ -0,0 +1,183 @@
+use crate::providers::config::ProviderError;
+use schemars::JsonSchema;
+use schemars::r#gen::SchemaSettings;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// A function the model is allowed to call
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    // JSON Schema of the arguments object
+    pub parameters: serde_json::Value,
+}
+
+// A function call requested by the model
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    // Arguments as a JSON encoded string, exactly as the API sends them
+    pub arguments: String,
+}
+
+fn function_type() -> String {
+    "function".to_string()
+}
+
+impl ToolDefinition {
+    // Definition in the OpenAI `tools` request format
+    pub fn to_openai(&self) -> serde_json::Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            }
+        })
+    }
+}
+
+impl ToolCall {
+    pub fn new(id: impl Into<String>, name: impl Into<String>, arguments: String) -> Self {
+        ToolCall {
+            id: id.into(),
+            kind: function_type(),
+            function: FunctionCall {
+                name: name.into(),
+                arguments,
+            },
+        }
+    }
+
+    // Decode the call into a variant of an internally tagged enum, using the
+    // function name as the value of the `tag` field
+    pub fn parse_tagged<T: DeserializeOwned>(&self, tag: &str) -> Result<T, ProviderError> {
+        let mut arguments: serde_json::Value = if self.function.arguments.trim().is_empty() {
+            json!({})
+        } else {
+            serde_json::from_str(&self.function.arguments).map_err(|e| {
+                ProviderError::ResponseParsing(format!(
+                    "Arguments of tool call {} are not valid JSON: {}",
+                    self.function.name, e
+                ))
+            })?
+        };
+        let object = arguments.as_object_mut().ok_or_else(|| {
+            ProviderError::ResponseParsing(format!(
+                "Arguments of tool call {} are not an object",
+                self.function.name
+            ))
+        })?;
+        object.insert(tag.to_string(), json!(self.function.name));
+        serde_json::from_value(arguments).map_err(|e| {
+            ProviderError::ResponseParsing(format!(
+                "Tool call {} does not match its definition: {}",
+                self.function.name, e
+            ))
+        })
+    }
+}
+
+// Build one tool per variant of an internally tagged enum (`#[serde(tag = "...")]`).
+// The tool name is the variant tag and the parameters are the variant fields.
+pub fn tools_from_tagged_enum<T: JsonSchema>(tag: &str) -> Vec<ToolDefinition> {
+    let generator = SchemaSettings::draft07()
+        .with(|s| s.inline_subschemas = true)
+        .into_generator();
+    let schema = serde_json::to_value(generator.into_root_schema_for::<T>()).unwrap_or_default();
+    let Some(variants) = schema.get("oneOf").and_then(|v| v.as_array()) else {
+        return Vec::new();
+    };
+
+    variants
+        .iter()
+        .filter_map(|variant| {
+            let mut parameters = variant.as_object()?.clone();
+            let name = parameters
+                .get("properties")?
+                .get(tag)?
+                .get("enum")?
+                .get(0)?
+                .as_str()?
+                .to_string();
+            let description = parameters
+                .remove("description")
+                .and_then(|d| d.as_str().map(str::to_string));
+            if let Some(properties) = parameters
+                .get_mut("properties")
+                .and_then(|p| p.as_object_mut())
+            {
+                properties.remove(tag);
+            }
+            if let Some(required) = parameters
+                .get_mut("required")
+                .and_then(|r| r.as_array_mut())
+            {
+                required.retain(|r| r != tag);
+            }
+            Some(ToolDefinition {
+                name,
+                description,
+                parameters: serde_json::Value::Object(parameters),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    struct Lookup {
+        query: String,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    enum Actions {
+        #[schemars(description = "look something up")]
+        Lookup(Lookup),
+        Ping {},
+    }
+
+    #[test]
+    fn test_tools_from_tagged_enum() {
+        let tools = tools_from_tagged_enum::<Actions>("kind");
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].name, "lookup");
+        assert_eq!(tools[0].description.as_deref(), Some("look something up"));
+        assert_eq!(tools[0].parameters["required"], json!(["query"]));
+        assert!(tools[0].parameters["properties"].get("kind").is_none());
+        assert_eq!(tools[1].name, "ping");
+    }
+
+    #[test]
+    fn test_parse_tagged() {
+        let call = ToolCall::new("call_1", "lookup", r#"{"query": "rust"}"#.to_string());
+        assert_eq!(
+            call.parse_tagged::<Actions>("kind").unwrap(),
+            Actions::Lookup(Lookup {
+                query: "rust".to_string()
+            })
+        );
+        let call = ToolCall::new("call_2", "ping", String::new());
+        assert_eq!(
+            call.parse_tagged::<Actions>("kind").unwrap(),
+            Actions::Ping {}
+        );
+        let call = ToolCall::new("call_3", "unknown", "{}".to_string());
+        assert!(call.parse_tagged::<Actions>("kind").is_err());
+    }
+}
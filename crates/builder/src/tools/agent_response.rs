@@ -1,5 +1,5 @@
 use super::fs::FsActions;
-use super::memory::MemoryAction;
+use super::memory::Memory;
 use schemars::JsonSchema;
 use schemars::schema_for;
 use serde::{Deserialize, Serialize};
@@ -30,7 +30,7 @@ pub enum AgentActions {
 #[serde(tag = "action_type", rename_all = "snake_case")]
 pub enum AgentTools {
     #[schemars(description = "agent memory")]
-    Memory(MemoryAction),
+    Memory(Memory),
 }
 
 
@@ -67,7 +67,8 @@ impl AgentResponse {
 
 #[cfg(test)]
 mod tests {
-    use crate::providers::openai::OpenAiProvider;
+    use crate::providers::chat::ChatProvider;
+    use crate::providers::structured::StructuredChat;
 
     use super::*;
     #[test]
@@ -78,13 +79,23 @@ mod tests {
 
     #[tokio::test]
     async fn test_memory() {
+        use crate::providers::cassette::{Cassette, RequestMatcher};
+        use crate::providers::conversation::Conversation;
         use crate::providers::openai::ChatMessage;
         use crate::providers::providers::Providers;
-        use crate::tools;
+        use std::sync::Arc;
         use anyhow;
         use tempdir::TempDir;
         let tmp_dir = TempDir::new("memory-test").unwrap();
-        let providers = Providers::load();
+        let providers = Providers::load().unwrap();
+        // Replays hand-written LmStudio answers, run with CASSETTE_MODE=record
+        // and a live server to replace them with real ones
+        let cassette = Cassette::from_env(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/test_memory.json"
+        ))
+        .unwrap()
+        .with_matcher(RequestMatcher::default().ignore_model());
         let lm_studio = providers
             .get_by_name("LmStudio")
             .expect("LmStudio provider not found");
@@ -116,25 +127,33 @@ mod tests {
         - remember the operation
         - return the result of the operation
         "#;
-        let provider = lm_studio
+        let model = lm_studio
             .with_model("bartowski/Tesslate_Gradience-T1-3B-preview-GGUF")
             .unwrap()
             .with_temperature(0.1) // Lower temperature for more deterministic answers
             .with_max_tokens(1000) // Limit response length
             .with_top_p(0.95)
-            .with_ctx(9000) // Slightly adjust nucleus sampling
-            .build()
-            .unwrap();
+            .with_cassette(Arc::new(cassette));
+        let provider = model.build().unwrap();
 
-        // Create messages for the chat completion
-        let messages = vec![ChatMessage::system(systemprompt), ChatMessage::user(prompt)];
-        let r = ask(provider.clone(), messages).await.unwrap();
-        for a in r.clone().actions.into_iter() {
+        // The history is trimmed to the model's context window before each request
+        let mut conversation =
+            Conversation::for_model(&model).with_response_schema(&AgentResponse::schema());
+        conversation
+            .push(ChatMessage::system(systemprompt))
+            .push(ChatMessage::user(prompt));
+        let r = ask(provider.as_ref(), &conversation).await.unwrap();
+        for t in r.tools.iter() {
             println!("====================");
-            match a {
-                AgentActions::Memory(m) => {
+            match t {
+                AgentTools::Memory(m) => {
                     println!("Memory action: {:?}", m);
                 }
+            }
+        }
+        {
+            println!("====================");
+            match r.actions.clone() {
                 AgentActions::Fs(f) => {
                     println!("File system action: {:?}", f);
                 }
@@ -144,24 +163,23 @@ mod tests {
                 AgentActions::UserAssistanceNeeded(u) => {
                     println!("User assistance needed: {:?}", u);
                 }
-                _ => {
-                    println!("Unknown action: {:?}", a);
-                }
             }
         }
-        let messages = vec![
-            ChatMessage::system(systemprompt),
-            ChatMessage::user(prompt),
-            ChatMessage::assistant(serde_json::json!(r).to_string()),
-            ChatMessage::user("first number: 1"),
-        ];
-        let r = ask(provider, messages).await.unwrap();
-        for a in r.clone().actions.into_iter() {
+        conversation
+            .push(ChatMessage::assistant(serde_json::json!(r).to_string()))
+            .push(ChatMessage::user("first number: 1"));
+        let r = ask(provider.as_ref(), &conversation).await.unwrap();
+        for t in r.tools.iter() {
             println!("====================");
-            match a {
-                AgentActions::Memory(m) => {
+            match t {
+                AgentTools::Memory(m) => {
                     println!("Memory action: {:?}", m);
                 }
+            }
+        }
+        {
+            println!("====================");
+            match r.actions.clone() {
                 AgentActions::Fs(f) => {
                     println!("File system action: {:?}", f);
                 }
@@ -171,46 +189,17 @@ mod tests {
                 AgentActions::UserAssistanceNeeded(u) => {
                     println!("User assistance needed: {:?}", u);
                 }
-                _ => {
-                    println!("Unknown action: {:?}", a);
-                }
             }
         }
-        // Query the model
+        // Query the model, invalid answers are sent back for repair twice
         async fn ask(
-            provider: OpenAiProvider,
-            messages: Vec<ChatMessage>,
+            provider: &dyn ChatProvider,
+            conversation: &Conversation,
         ) -> anyhow::Result<AgentResponse> {
-            let askr = match provider
-                .chat_completion(
-                    messages,
-                    None,
-                    None,
-                    None,
-                    Some(tools::agent_response::AgentResponse::schema()),
-                )
-                .await
-            {
-                Ok(response) => {
-                    println!("Response from: {}", response);
-                    let r: AgentResponse = match serde_json::from_str(&response) {
-                        Ok(r) => {
-                            println!("Parsed response: {:?}", r);
-                            r
-                        }
-                        Err(e) => {
-                            println!("Error parsing response: {}", e);
-                            return Err(anyhow::anyhow!("Error parsing response"));
-                        }
-                    };
-                    Ok(r)
-                }
-                Err(e) => {
-                    println!("Error {}", e);
-                    Err(e)
-                }
-            };
-            askr.map_err(|e| anyhow::anyhow!(e))
+            let messages = conversation.request_messages()?;
+            let r: AgentResponse = provider.chat_structured(messages, 2).await?;
+            println!("Parsed response: {:?}", r);
+            Ok(r)
         }
     }
 }
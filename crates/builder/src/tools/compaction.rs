@@ -0,0 +1,532 @@
+use super::agent_response::{AgentActions, AgentResponse, AgentTools};
+use super::memory::{Memory, MemoryAction, MemoryParams};
+use super::tool_calls::{AgentToolCall, parse_tool_call};
+use crate::providers::chat::ChatProvider;
+use crate::providers::config::ProviderError;
+use crate::providers::conversation::{Conversation, DEFAULT_CONTEXT_WINDOW};
+use crate::providers::openai::ChatMessage;
+use crate::providers::provider::ModelBuilder;
+use crate::providers::providers::Providers;
+use crate::providers::tokenizer::{CharEstimator, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tracing::warn;
+
+// First line of the summary message, also how the next compaction finds it
+const STATE_HEADER: &str = "State so far, replacing the earlier part of this conversation:";
+const MEMORIES_HEADER: &str = "Stored memories, verbatim:";
+const FILES_HEADER: &str = "Files touched:";
+const NEXT_HEADER: &str = "Planned next step, verbatim:";
+// Long tool results are cut in the transcript sent to the summarizer
+const MAX_MESSAGE_CHARS: usize = 4000;
+// Role markers and the line carrying the previous summary
+const PROMPT_OVERHEAD: usize = 32;
+
+const SUMMARY_PROMPT: &str = "You compact the history of a coding agent. \
+Summarize the conversation below so the agent can continue without it. \
+Keep the goal, the decisions made and why, the progress, unresolved errors \
+and a list of the open tasks. Mention the file paths that matter. \
+Do not add anything that is not in the conversation. Answer with the summary only.";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StoredMemory {
+    pub id: String,
+    pub content: String,
+}
+
+// What the agent knows about the compacted part of the session. Memories,
+// files and the next step come from the agent's answers, only the summary
+// is written by the model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionState {
+    pub summary: String,
+    pub memories: Vec<StoredMemory>,
+    pub files: BTreeSet<String>,
+    // `next` of the last compacted AgentResponse
+    pub next: Option<String>,
+}
+
+impl SessionState {
+    fn apply_memory(&mut self, memory: &Memory) {
+        match (&memory.action, &memory.params) {
+            (MemoryAction::Store, MemoryParams::StoreParams { content, id }) => {
+                self.memories.retain(|m| m.id != *id);
+                self.memories.push(StoredMemory {
+                    id: id.clone(),
+                    content: content.clone(),
+                });
+            }
+            (MemoryAction::Forget, MemoryParams::ForgetParams { id }) => {
+                self.memories.retain(|m| m.id != *id);
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_action(&mut self, action: &AgentActions) {
+        if let AgentActions::Fs(fs) = action {
+            self.files
+                .extend(fs.file_paths().into_iter().map(String::from));
+        }
+    }
+
+    // Record the memories and files of a message, native tool calls and
+    // schema mode AgentResponses alike
+    pub fn collect(&mut self, message: &ChatMessage) {
+        if let Some(state) = SessionState::parse(message) {
+            state.memories.iter().for_each(|m| {
+                self.memories.retain(|old| old.id != m.id);
+                self.memories.push(m.clone());
+            });
+            self.files.extend(state.files);
+            if state.next.is_some() {
+                self.next = state.next;
+            }
+            return;
+        }
+        if message.role != "assistant" {
+            return;
+        }
+        for call in message.tool_calls.iter().flatten() {
+            match parse_tool_call(call) {
+                Ok(AgentToolCall::Action(action)) => self.apply_action(&action),
+                Ok(AgentToolCall::Tool(AgentTools::Memory(memory))) => self.apply_memory(&memory),
+                Err(_) => {}
+            }
+        }
+        if let Ok(response) = serde_json::from_str::<AgentResponse>(&message.content.to_text()) {
+            self.apply_action(&response.actions);
+            for AgentTools::Memory(memory) in &response.tools {
+                self.apply_memory(memory);
+            }
+            self.next = Some(response.next);
+        }
+    }
+
+    // State stored in a summary message by `to_message`
+    pub fn parse(message: &ChatMessage) -> Option<SessionState> {
+        if message.role != "system" {
+            return None;
+        }
+        let text = message.content.to_text();
+        let rest = text.strip_prefix(STATE_HEADER)?.strip_prefix('\n')?;
+        let (rest, next) = rest.rsplit_once(&format!("\n\n{}\n", NEXT_HEADER))?;
+        let (rest, files) = rest.rsplit_once(&format!("\n\n{}\n", FILES_HEADER))?;
+        let (summary, memories) = rest.rsplit_once(&format!("\n\n{}\n", MEMORIES_HEADER))?;
+        Some(SessionState {
+            summary: summary.to_string(),
+            memories: serde_json::from_str(memories).ok()?,
+            files: serde_json::from_str(files).ok()?,
+            next: serde_json::from_str(next).ok()?,
+        })
+    }
+
+    // System message with the summary, the memories, files and next step
+    // are JSON so they survive later compactions unchanged
+    pub fn to_message(&self) -> ChatMessage {
+        ChatMessage::system(format!(
+            "{}\n{}\n\n{}\n{}\n\n{}\n{}\n\n{}\n{}",
+            STATE_HEADER,
+            self.summary,
+            MEMORIES_HEADER,
+            serde_json::to_string(&self.memories).unwrap_or_default(),
+            FILES_HEADER,
+            serde_json::to_string(&self.files).unwrap_or_default(),
+            NEXT_HEADER,
+            serde_json::to_string(&self.next).unwrap_or_default()
+        ))
+    }
+}
+
+fn cut(text: &mut String, max_chars: usize) {
+    if let Some((end, _)) = text.char_indices().nth(max_chars) {
+        text.truncate(end);
+        text.push_str(" [...]");
+    }
+}
+
+// Plain text version of the messages for the summarizer, one line each
+fn transcript(messages: &[&ChatMessage]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for message in messages {
+        if let Some(state) = SessionState::parse(message) {
+            lines.push(format!("summary of earlier work: {}", state.summary));
+            continue;
+        }
+        let mut text = message.content.to_text();
+        cut(&mut text, MAX_MESSAGE_CHARS);
+        if !text.is_empty() {
+            lines.push(format!("{}: {}", message.role, text));
+        }
+        for call in message.tool_calls.iter().flatten() {
+            lines.push(format!(
+                "{} calls {} {}",
+                message.role, call.function.name, call.function.arguments
+            ));
+        }
+    }
+    lines
+}
+
+// Group transcript lines into pieces of about `budget` tokens, a line that
+// is longer on its own is cut
+fn chunks(lines: Vec<String>, budget: usize) -> Vec<String> {
+    let tokenizer = CharEstimator::default();
+    let max_chars = (budget as f32 * tokenizer.chars_per_token) as usize;
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for mut line in lines {
+        cut(&mut line, max_chars.saturating_sub(8));
+        if !chunk.is_empty() && tokenizer.count(&chunk) + tokenizer.count(&line) + 1 > budget {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(&line);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Replaces the oldest turns of a Conversation with a summary once it fills
+// up. The summarizer can be any ChatProvider, usually a cheap route.
+#[derive(Debug, Clone)]
+pub struct Compactor {
+    summarizer: Arc<dyn ChatProvider>,
+    // Share of the prompt budget that triggers a compaction
+    threshold: f32,
+    // Recent turns that are always sent as they are
+    keep_last_turns: usize,
+    max_tokens: u32,
+    // Of the summarizer, longer transcripts are summarized in chunks
+    context_window: usize,
+}
+
+impl Compactor {
+    pub fn new(summarizer: Box<dyn ChatProvider>) -> Self {
+        Compactor {
+            summarizer: Arc::from(summarizer),
+            threshold: 0.8,
+            keep_last_turns: 2,
+            max_tokens: 1024,
+            context_window: DEFAULT_CONTEXT_WINDOW,
+        }
+    }
+
+    // Summarize with a model, its context window limits the chunks
+    pub fn for_model(model: &ModelBuilder) -> Result<Self, ProviderError> {
+        let context_window = model
+            .context_window()
+            .map_or(DEFAULT_CONTEXT_WINDOW, |w| w as usize);
+        Ok(Self::new(model.build()?).with_context_window(context_window))
+    }
+
+    // Summarize with the targets of a route from providers.toml
+    pub fn from_route(providers: &Providers, route: &str) -> Result<Self, ProviderError> {
+        Ok(Self::new(Box::new(providers.route(route)?)))
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_keep_last_turns(mut self, turns: usize) -> Self {
+        self.keep_last_turns = turns;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_context_window(mut self, tokens: usize) -> Self {
+        self.context_window = tokens;
+        self
+    }
+
+    // Transcript tokens per summarizer call: the window minus the prompt,
+    // the answer and the summary of the previous chunk
+    fn chunk_budget(&self) -> usize {
+        self.context_window
+            .saturating_sub(2 * self.max_tokens as usize)
+            .saturating_sub(CharEstimator::default().count(SUMMARY_PROMPT) + PROMPT_OVERHEAD)
+    }
+
+    async fn summarize(&self, transcript: String) -> Result<String, ProviderError> {
+        let summary = self
+            .summarizer
+            .chat_completion(
+                vec![
+                    ChatMessage::system(SUMMARY_PROMPT),
+                    ChatMessage::user(transcript),
+                ],
+                None,
+                None,
+                Some(self.max_tokens),
+                None,
+            )
+            .await?;
+        Ok(summary.trim().to_string())
+    }
+
+    pub fn needs_compaction(&self, conversation: &Conversation) -> bool {
+        conversation.tokens() as f32 >= conversation.budget() as f32 * self.threshold
+    }
+
+    // Summarize everything before the last turns into one system message,
+    // merged with the summary of an earlier compaction. A transcript too
+    // long for the summarizer is summarized chunk by chunk, each call gets
+    // the summary so far. Returns false when there is nothing old enough
+    // to compact.
+    pub async fn compact(&self, conversation: &mut Conversation) -> Result<bool, ProviderError> {
+        let range = conversation.compactable(self.keep_last_turns);
+        if range.is_empty() {
+            return Ok(false);
+        }
+        let budget = self.chunk_budget();
+        if budget == 0 {
+            return Err(ProviderError::Configuration(format!(
+                "Context window of {} is too small to summarize with max_tokens {}",
+                self.context_window, self.max_tokens
+            )));
+        }
+        let messages = conversation.messages();
+        let previous = messages[..range.start]
+            .iter()
+            .position(|m| SessionState::parse(m).is_some());
+        let old: Vec<&ChatMessage> = previous
+            .map(|i| &messages[i])
+            .into_iter()
+            .chain(&messages[range.clone()])
+            .collect();
+
+        let mut state = SessionState::default();
+        old.iter().for_each(|m| state.collect(m));
+        for chunk in chunks(transcript(&old), budget) {
+            let text = if state.summary.is_empty() {
+                chunk
+            } else {
+                format!("summary of earlier work: {}\n{}", state.summary, chunk)
+            };
+            state.summary = self.summarize(text).await?;
+        }
+
+        conversation.splice(range.clone(), []);
+        match previous {
+            Some(i) => conversation.splice(i..i + 1, [state.to_message()]),
+            None => conversation.splice(range.start..range.start, [state.to_message()]),
+        }
+        Ok(true)
+    }
+
+    // Conversation::request_messages, compacting first when the history
+    // has reached the threshold. When the summarizer fails the truncation
+    // policy of the conversation drops old turns instead.
+    pub async fn request_messages(
+        &self,
+        conversation: &mut Conversation,
+    ) -> Result<Vec<ChatMessage>, ProviderError> {
+        if self.needs_compaction(conversation) {
+            match self.compact(conversation).await {
+                Ok(_) => {}
+                Err(ProviderError::Cancelled) => return Err(ProviderError::Cancelled),
+                Err(e) => warn!("Compaction failed, truncating instead: {}", e),
+            }
+        }
+        conversation.request_messages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::openai::tests::mock_provider;
+    use crate::providers::tool_call::ToolCall;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    fn call(name: &str, arguments: serde_json::Value) -> ChatMessage {
+        let mut message = ChatMessage::assistant("");
+        message.tool_calls = Some(vec![ToolCall::new("1", name, arguments.to_string())]);
+        message
+    }
+
+    fn turn(n: usize) -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::user(format!("step {}", n)),
+            call(
+                "memory",
+                json!({"action": "store", "id": format!("step-{}", n), "content": format!("done {}\nok", n)}),
+            ),
+            ChatMessage::tool("1", "stored"),
+            call(
+                "write_file",
+                json!({"file_path": format!("src/{}.rs", n), "content": ""}),
+            ),
+            ChatMessage::tool("1", "written"),
+        ]
+    }
+
+    #[test]
+    fn test_session_state() {
+        let mut state = SessionState::default();
+        turn(1).iter().for_each(|m| state.collect(m));
+        state.collect(&call("memory", json!({"action": "forget", "id": "step-1"})));
+        let response = json!({
+            "reasoning": [],
+            "actions": {"action_type": "search_web", "query": "serde"},
+            "tools": [{"action_type": "memory", "action": "store", "id": "plan", "content": "1. read"}],
+            "next": "read"
+        });
+        state.collect(&ChatMessage::assistant(response.to_string()));
+        state.summary = "Looking up serde".to_string();
+
+        assert_eq!(
+            state.memories,
+            vec![StoredMemory {
+                id: "plan".to_string(),
+                content: "1. read".to_string()
+            }]
+        );
+        assert!(state.files.contains("src/1.rs"));
+        assert_eq!(state.next.as_deref(), Some("read"));
+        assert_eq!(SessionState::parse(&state.to_message()), Some(state));
+        assert_eq!(SessionState::parse(&ChatMessage::system("be brief")), None);
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::Regex("step 1".to_string()))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "Wrote 1 and 2"}}]})
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+        let summarizer = mock_provider(&server, "small");
+        let compactor = Compactor::new(Box::new(summarizer)).with_keep_last_turns(1);
+
+        let mut conversation = Conversation::new(10_000).with_reserved_output(0);
+        conversation.push(ChatMessage::system("be brief"));
+        (1..=3).for_each(|n| {
+            conversation.extend(turn(n));
+        });
+        assert!(!compactor.needs_compaction(&conversation));
+        let compactor = compactor.with_threshold(0.0);
+        let messages = compactor.request_messages(&mut conversation).await.unwrap();
+        first.assert_async().await;
+
+        assert_eq!(messages.len(), 2 + 5);
+        let state = SessionState::parse(&messages[1]).unwrap();
+        assert_eq!(state.summary, "Wrote 1 and 2");
+        assert_eq!(state.memories.len(), 2);
+        assert_eq!(state.memories[1].content, "done 2\nok");
+        assert_eq!(
+            state.files.into_iter().collect::<Vec<_>>(),
+            vec!["src/1.rs", "src/2.rs"]
+        );
+
+        // The next compaction folds the old summary in instead of adding one
+        let second = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::Regex(
+                "summary of earlier work: Wrote 1 and 2".to_string(),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "Wrote 1 to 3"}}]})
+                    .to_string(),
+            )
+            .create_async()
+            .await;
+        conversation.extend(turn(4));
+        assert!(compactor.compact(&mut conversation).await.unwrap());
+        second.assert_async().await;
+        assert_eq!(conversation.len(), 2 + 5);
+        let state = SessionState::parse(&conversation.messages()[1]).unwrap();
+        assert_eq!(state.summary, "Wrote 1 to 3");
+        assert_eq!(state.memories.len(), 3);
+        assert_eq!(state.files.len(), 3);
+
+        // Only the last turn is left, nothing more to compact
+        assert!(!compactor.compact(&mut conversation).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_chunks_and_fallback() {
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::Regex(r#""content":"user: step 1"#.to_string()))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "part one"}}]})
+                    .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let rest = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::Regex(
+                r#""content":"summary of earlier work: "#.to_string(),
+            ))
+            .with_status(200)
+            .with_body(
+                json!({"choices": [{"message": {"role": "assistant", "content": "all of it"}}]})
+                    .to_string(),
+            )
+            .expect(2)
+            .create_async()
+            .await;
+        let summarizer = mock_provider(&server, "small");
+        // Leaves about 60 tokens of transcript per call, the two turns take three
+        let compactor = Compactor::new(Box::new(summarizer))
+            .with_keep_last_turns(1)
+            .with_max_tokens(100)
+            .with_context_window(377);
+
+        let mut conversation = Conversation::new(10_000).with_reserved_output(0);
+        conversation.extend(turn(1));
+        let response = json!({
+            "reasoning": [],
+            "actions": {"action_type": "search_web", "query": "serde"},
+            "tools": [],
+            "next": "write src/3.rs"
+        });
+        conversation.push(ChatMessage::assistant(response.to_string()));
+        conversation.extend(turn(2));
+        conversation.extend(turn(3));
+        assert!(compactor.compact(&mut conversation).await.unwrap());
+        first.assert_async().await;
+        rest.assert_async().await;
+        let state = SessionState::parse(&conversation.messages()[0]).unwrap();
+        assert_eq!(state.summary, "all of it");
+        assert_eq!(state.next.as_deref(), Some("write src/3.rs"));
+
+        // A failing summarizer leaves the history to the truncation policy
+        let mut down = mockito::Server::new_async().await;
+        down.mock("POST", "/v1/chat/completions")
+            .with_status(500)
+            .create_async()
+            .await;
+        let compactor = Compactor::new(Box::new(mock_provider(&down, "small")))
+            .with_keep_last_turns(1)
+            .with_threshold(0.0);
+        conversation.extend(turn(4));
+        let len = conversation.len();
+        let messages = compactor.request_messages(&mut conversation).await.unwrap();
+        assert_eq!(messages.len(), len);
+        assert_eq!(conversation.len(), len);
+    }
+}
@@ -192,3 +192,16 @@ pub enum FsActions {
     #[schemars(description = "get current directory")]
     Pwd(Pwd),
 }
+
+impl FsActions {
+    // Files read or changed by the action, directories are left out
+    pub fn file_paths(&self) -> Vec<&str> {
+        match self {
+            FsActions::ReadFile(f) => vec![f.file_path.as_str()],
+            FsActions::WriteFile(f) => vec![f.file_path.as_str()],
+            FsActions::ApplyPatchToFile(f) => vec![f.file_path.as_str()],
+            FsActions::DiffFiles(f) => vec![f.file_0_path.as_str(), f.file_1_path.as_str()],
+            FsActions::DirLs(_) | FsActions::CD(_) | FsActions::Pwd(_) => Vec::new(),
+        }
+    }
+}
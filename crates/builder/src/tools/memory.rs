@@ -1,7 +1,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Memory {
     #[schemars(description = "The type of action to perform (e.g., list, find, forget, store).")]
     pub action: MemoryAction,
@@ -24,8 +24,14 @@ pub enum MemoryAction {
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 #[serde(untagged)] // Untagged enum for flexible parameter handling
+// Variants are tried in order, so the ones with most fields come first
 pub enum MemoryParams {
-    ListParams {}, // Empty struct for "list" action
+    StoreParams {
+        #[schemars(description = "The content to store.")]
+        content: String,
+        #[schemars(description = "The ID to associate with the stored memory.")]
+        id: String,
+    },
     FindParams {
         #[schemars(description = "The query to find specific memory.")]
         query: String,
@@ -34,10 +40,5 @@ pub enum MemoryParams {
         #[schemars(description = "The ID of the memory to forget.")]
         id: String,
     },
-    StoreParams {
-        #[schemars(description = "The content to store.")]
-        content: String,
-        #[schemars(description = "The ID to associate with the stored memory.")]
-        id: String,
-    },
+    ListParams {}, // Empty struct for "list" action
 }
@@ -1,3 +1,5 @@
 pub mod agent_response;
+pub mod compaction;
 pub mod fs;
 pub mod memory;
+pub mod tool_calls;
@@ -0,0 +1,261 @@
+use super::agent_response::{AgentActions, AgentResponse, AgentTools};
+use super::fs::FsActions;
+use crate::project::ActionResult;
+use crate::providers::catalog::ModelCapabilities;
+use crate::providers::chat::ChatProvider;
+use crate::providers::config::ProviderError;
+use crate::providers::openai::ChatMessage;
+use crate::providers::provider::ModelBuilder;
+use crate::providers::tool_call::{ToolCall, ToolDefinition, tools_from_tagged_enum};
+
+// Field used by the agent enums to tell their variants apart
+const ACTION_TAG: &str = "action_type";
+// AgentActions variant that wraps FsActions, its variants are offered as separate tools
+const FS_ACTION: &str = "fs";
+
+// How the agent asks the model for its next actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolMode {
+    // Native tool/function calling
+    #[default]
+    Native,
+    // A single AgentResponse forced through `response_format: json_schema`,
+    // for models without tool support
+    Schema,
+}
+
+impl ToolMode {
+    // Native tool calls unless the model is known to lack them, see
+    // ModelBuilder::capabilities
+    pub fn for_model(capabilities: Option<ModelCapabilities>) -> Self {
+        match capabilities {
+            Some(capabilities) if !capabilities.tools => ToolMode::Schema,
+            _ => ToolMode::Native,
+        }
+    }
+}
+
+// The agent's provider, asked in the tool mode its model supports
+#[derive(Debug)]
+pub struct AgentModel {
+    provider: Box<dyn ChatProvider>,
+    mode: ToolMode,
+}
+
+impl AgentModel {
+    pub fn new(model: &ModelBuilder) -> Result<Self, ProviderError> {
+        Ok(AgentModel {
+            provider: model.build()?,
+            mode: ToolMode::for_model(model.capabilities()),
+        })
+    }
+
+    // Overrides the mode picked from the catalog
+    pub fn with_mode(mut self, mode: ToolMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn mode(&self) -> ToolMode {
+        self.mode
+    }
+
+    pub fn provider(&self) -> &dyn ChatProvider {
+        self.provider.as_ref()
+    }
+
+    pub async fn next_step(&self, messages: Vec<ChatMessage>) -> Result<AgentStep, ProviderError> {
+        next_step(self.provider(), messages, self.mode).await
+    }
+}
+
+// A typed action requested by the model
+#[derive(Debug, Clone)]
+pub enum AgentToolCall {
+    Action(AgentActions),
+    Tool(AgentTools),
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentCall {
+    // Id of the native tool call, None in schema mode
+    pub id: Option<String>,
+    pub call: AgentToolCall,
+}
+
+// Reply of the model: the assistant message to keep in the history and the
+// actions it asked for
+#[derive(Debug, Clone)]
+pub struct AgentStep {
+    pub message: ChatMessage,
+    pub calls: Vec<AgentCall>,
+}
+
+// Tool definitions for every FsActions, AgentActions and AgentTools variant
+pub fn agent_tool_definitions() -> Vec<ToolDefinition> {
+    let mut tools = tools_from_tagged_enum::<FsActions>(ACTION_TAG);
+    tools.extend(
+        tools_from_tagged_enum::<AgentActions>(ACTION_TAG)
+            .into_iter()
+            .filter(|tool| tool.name != FS_ACTION),
+    );
+    tools.extend(tools_from_tagged_enum::<AgentTools>(ACTION_TAG));
+    tools
+}
+
+// Map a tool call back to the agent enums
+pub fn parse_tool_call(call: &ToolCall) -> Result<AgentToolCall, ProviderError> {
+    if let Ok(fs) = call.parse_tagged::<FsActions>(ACTION_TAG) {
+        return Ok(AgentToolCall::Action(AgentActions::Fs(fs)));
+    }
+    if let Ok(tool) = call.parse_tagged::<AgentTools>(ACTION_TAG) {
+        return Ok(AgentToolCall::Tool(tool));
+    }
+    call.parse_tagged::<AgentActions>(ACTION_TAG)
+        .map(AgentToolCall::Action)
+}
+
+impl AgentCall {
+    // Message that reports the result of this call back to the model
+    pub fn result_message(&self, result: &ActionResult) -> ChatMessage {
+        let content = serde_json::to_string(result).unwrap_or_default();
+        match &self.id {
+            Some(id) => ChatMessage::tool(id.clone(), content),
+            None => ChatMessage::user(content),
+        }
+    }
+}
+
+impl From<AgentResponse> for Vec<AgentCall> {
+    fn from(response: AgentResponse) -> Self {
+        let mut calls = vec![AgentCall {
+            id: None,
+            call: AgentToolCall::Action(response.actions),
+        }];
+        calls.extend(response.tools.into_iter().map(|tool| AgentCall {
+            id: None,
+            call: AgentToolCall::Tool(tool),
+        }));
+        calls
+    }
+}
+
+// Ask the model for its next actions
+pub async fn next_step(
+    provider: &dyn ChatProvider,
+    messages: Vec<ChatMessage>,
+    mode: ToolMode,
+) -> Result<AgentStep, ProviderError> {
+    match mode {
+        ToolMode::Native => {
+            let message = provider
+                .chat_with_tools(messages, agent_tool_definitions())
+                .await?;
+            let calls = message
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(|call| {
+                    parse_tool_call(call).map(|parsed| AgentCall {
+                        id: Some(call.id.clone()),
+                        call: parsed,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(AgentStep { message, calls })
+        }
+        ToolMode::Schema => {
+            // The whole message, so the reasoning stays in the history
+            let message = provider
+                .complete(messages, None, None, None, Some(AgentResponse::schema()))
+                .await?
+                .into_message()?;
+            let parsed: AgentResponse =
+                serde_json::from_str(&message.content.to_text()).map_err(|e| {
+                    ProviderError::ResponseParsing(format!("Invalid AgentResponse: {}", e))
+                })?;
+            Ok(AgentStep {
+                message,
+                calls: parsed.into(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::memory::{MemoryAction, MemoryParams};
+
+    #[test]
+    fn test_agent_tool_definitions() {
+        let tools = agent_tool_definitions();
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert!(names.contains(&"read_file"));
+        assert!(names.contains(&"search_web"));
+        assert!(names.contains(&"user_assistance_needed"));
+        assert!(names.contains(&"memory"));
+        assert!(!names.contains(&FS_ACTION));
+    }
+
+    #[test]
+    fn test_tool_mode_for_model() {
+        use crate::providers::catalog::{KnownModel, MistralModel, QwenModel};
+        use crate::providers::provider::{Provider, ProviderType};
+        let capabilities = |model: MistralModel| Some(KnownModel::Mistral(model).capabilities());
+        assert_eq!(
+            ToolMode::for_model(capabilities(MistralModel::Codestral22b)),
+            ToolMode::Schema
+        );
+        assert_eq!(
+            ToolMode::for_model(capabilities(MistralModel::MistralSmall)),
+            ToolMode::Native
+        );
+        assert_eq!(
+            ToolMode::for_model(Some(QwenModel::Qwen3.capabilities())),
+            ToolMode::Native
+        );
+        assert_eq!(ToolMode::for_model(None), ToolMode::Native);
+
+        let ollama = Provider::provider(ProviderType::Ollama);
+        let agent = AgentModel::new(&ollama.with_model("codestral:22b").unwrap()).unwrap();
+        assert_eq!(agent.mode(), ToolMode::Schema);
+        let agent = AgentModel::new(&ollama.with_model("qwen3:8b").unwrap()).unwrap();
+        assert_eq!(agent.mode(), ToolMode::Native);
+    }
+
+    #[test]
+    fn test_parse_tool_call() {
+        let call = ToolCall::new(
+            "1",
+            "read_file",
+            r#"{"file_path":"src/main.rs"}"#.to_string(),
+        );
+        assert!(matches!(
+            parse_tool_call(&call).unwrap(),
+            AgentToolCall::Action(AgentActions::Fs(FsActions::ReadFile(f))) if f.file_path == "src/main.rs"
+        ));
+
+        let call = ToolCall::new("2", "search_web", r#"{"query":"tokio"}"#.to_string());
+        assert!(matches!(
+            parse_tool_call(&call).unwrap(),
+            AgentToolCall::Action(AgentActions::SearchWeb(_))
+        ));
+
+        let call = ToolCall::new(
+            "3",
+            "memory",
+            r#"{"action":"store","id":"first","content":"1"}"#.to_string(),
+        );
+        match parse_tool_call(&call).unwrap() {
+            AgentToolCall::Tool(AgentTools::Memory(memory)) => {
+                assert!(matches!(memory.action, MemoryAction::Store));
+                assert!(matches!(
+                    memory.params,
+                    MemoryParams::StoreParams { content, .. } if content == "1"
+                ));
+            }
+            other => panic!("unexpected call {:?}", other),
+        }
+    }
+}